@@ -4,10 +4,24 @@ MEMORY
   /* TODO Adjust these memory regions to match your device memory layout */
   /* These values correspond to the LM3S6965, one of the few devices QEMU can emulate */
    /* FLASH : ORIGIN = 0x00000000, LENGTH = 256K */ /* Total flash */
-   FLASH : ORIGIN = 0x00000000, LENGTH = 256K /* User code section*/
-  RAM : ORIGIN = 0x10000000, LENGTH = 32K
+   /* Sector 0 holds the bootloader, which installs firmware updates, see boot.rs */
+   BOOT : ORIGIN = 0x00000000, LENGTH = 4K
+   /* The upper 128K receive firmware updates before they are installed, see update.rs */
+   FLASH : ORIGIN = 0x00001000, LENGTH = 124K /* User code section*/
+  /* The IAP routines in ROM use the top 32 bytes of RAM, so the stack starts below them */
+  RAM : ORIGIN = 0x10000000, LENGTH = 32K - 32
 }
 
+/* The bootloader comes first, the firmware with its own vector table follows in FLASH */
+SECTIONS
+{
+  .boot ORIGIN(BOOT) :
+  {
+    KEEP(*(.boot.vector_table));
+    *(.boot.text .boot.text.*);
+  } > BOOT
+} INSERT BEFORE .vector_table;
+
 /* This is where the call stack will be allocated. */
 /* The stack is of the full descending type. */
 /* You may want to use this variable to locate the call stack and static
@@ -0,0 +1,1015 @@
+//! Registers behind the two addresses of the EVBot connector: the Epower register map, its PMBus
+//! personality and the proxied IR3595.
+//!
+//! `Evbot` serves them as a `Handler`, everything it reads or changes outside of the registers is
+//! reached through `Board`. `EvbotState` keeps what has to outlive a single interrupt.
+
+use crate::pmbus::{self, commands, status_cml, status_word};
+use crate::settings::{
+    self, SettingError, EXPORT_SIZE, PEC_OFFSET, PMBUS_PERSONALITY_OFFSET, PRESET_COUNT,
+    PRESET_NAMES_SIZE, PRESET_NAME_SIZE, PROXY_ACCESS_RULES_RANGE, PROXY_ACCESS_RULE_SIZE,
+    PROXY_LOCK_OFFSET,
+};
+use crate::{Device, Handler, FILLER_BYTE};
+
+/// Number of samples in the telemetry history
+pub const HISTORY_COUNT_CMD: u8 = 0xD0;
+/// Streams the telemetry history, starting at the sample index (2 bytes, MSB first, oldest is 0)
+/// written to this command before the read
+pub const HISTORY_DATA_CMD: u8 = 0xD1;
+/// Epower command resuming (0), pausing (1) or clearing (2) the history recording,
+/// reads back whether the recording is paused
+pub const HISTORY_CONTROL_CMD: u8 = 0xD2;
+/// Number of entries in the journal of proxied IR3595 writes
+pub const JOURNAL_COUNT_CMD: u8 = 0xD3;
+/// Streams the journal, starting at the entry index (2 bytes, MSB first, oldest is 0) written to
+/// this command before the read
+pub const JOURNAL_DATA_CMD: u8 = 0xD4;
+/// Epower command resuming (0), pausing (1), clearing (2) or printing over UART (3) the
+/// journal, reads back whether the journal is paused
+pub const JOURNAL_CONTROL_CMD: u8 = 0xD5;
+/// Streams the name of preset 1, the following commands up to `LAST_PRESET_NAME_CMD` are the
+/// names of the other presets. Writes take up to `settings::PRESET_NAME_SIZE` ASCII characters.
+pub const PRESET_NAMES_CMD: u8 = 0xD8;
+pub const LAST_PRESET_NAME_CMD: u8 = PRESET_NAMES_CMD + PRESET_COUNT - 1;
+/// Epower command selecting the active voltage preset, reads back the active preset
+pub const PRESET_SELECT_CMD: u8 = 0xE0;
+/// Epower command configuring a voltage sweep: loop, start VID, stop VID, VID step, dwell ms (MSB
+/// first, at least `sweep::MIN_DWELL_MS` and rounded up to whole telemetry samples)
+pub const SWEEP_CONFIG_CMD: u8 = 0xE1;
+/// Epower command starting (1) or aborting (0) a voltage sweep, reads back the sweep state
+pub const SWEEP_CONTROL_CMD: u8 = 0xE2;
+/// Number of recorded sweep steps
+pub const SWEEP_COUNT_CMD: u8 = 0xE3;
+/// Streams all recorded sweep steps back to back
+pub const SWEEP_RESULTS_CMD: u8 = 0xE4;
+/// Epower command restarting the energy accounting of both loops
+pub const ENERGY_RESET_CMD: u8 = 0xE5;
+/// Epower command clearing the latched IR3595 faults
+pub const CLEAR_FAULTS_CMD: u8 = 0xE6;
+/// Streams the exported settings, see `settings::EXPORT_SIZE`. Writing 1 prints them over UART.
+pub const SETTINGS_EXPORT_CMD: u8 = 0xE7;
+/// Epower command storing settings kept back by the manual commit setting,
+/// reads back whether there are uncommitted settings
+pub const COMMIT_SETTINGS_CMD: u8 = 0xE8;
+/// Epower command reverting settings kept back by the manual commit setting
+pub const DISCARD_SETTINGS_CMD: u8 = 0xE9;
+/// Epower command restoring and storing the default settings
+pub const RESTORE_DEFAULTS_CMD: u8 = 0xEA;
+/// Epower command resetting the microcontroller, uncommitted settings are lost
+pub const SOFT_RESET_CMD: u8 = 0xEB;
+/// Epower command entering the UART ISP bootloader in ROM
+pub const ENTER_ISP_CMD: u8 = 0xEC;
+/// Epower command importing settings exported by `SETTINGS_EXPORT_CMD`, they are stored right away
+pub const SETTINGS_IMPORT_CMD: u8 = 0xED;
+/// Epower command starting a firmware update: image length and CRC32, both 4 bytes MSB first
+pub const UPDATE_START_CMD: u8 = 0xF1;
+/// Epower command carrying block number (2 bytes, MSB first) and `update::BLOCK_SIZE` image bytes
+pub const UPDATE_DATA_CMD: u8 = 0xF2;
+/// Update state followed by the next expected block number (MSB first)
+pub const UPDATE_STATUS_CMD: u8 = 0xF3;
+/// Epower command installing the verified image, needs the confirmation key like 0xE8 to 0xEC
+pub const UPDATE_INSTALL_CMD: u8 = 0xF4;
+
+/// PMBus personality: page 0 is loop 1, page 1 is loop 2
+const PMBUS_PAGE_COUNT: u8 = 2;
+/// PMBus personality: exponent of all LINEAR16 output voltages, 2^-12 V is about 0.25 mV
+const PMBUS_VOUT_EXPONENT: i8 = -12;
+/// PMBus personality: PMBus 1.2 for part I and II
+const PMBUS_REVISION: u8 = 0x22;
+const PMBUS_MFR_ID: &[u8] = b"EVGA";
+const PMBUS_MFR_MODEL: &[u8] = b"EPOWER V";
+/// PMBus personality: MFR_SPECIFIC command, writing 0 switches back to the Epower register map
+pub const PMBUS_PERSONALITY_CMD: u8 = 0xF0;
+
+/// Number of writes to the Epower rejected because of a bad PEC
+pub const EPOWER_PEC_ERRORS_CMD: u8 = 0xA0;
+/// Number of proxied IR3595 writes rejected because of a bad PEC
+pub const IR3595_PEC_ERRORS_CMD: u8 = 0xA1;
+/// Number of bus errors (illegal START or STOP) seen on the EVBot connector
+pub const BUS_ERRORS_CMD: u8 = 0xA2;
+/// Number of proxied IR3595 accesses refused by the access rules
+pub const DENIED_IR3595_ACCESSES_CMD: u8 = 0xA3;
+/// Number of times I2C0 was recovered from a stuck bus
+pub const BUS_RECOVERIES_CMD: u8 = 0xA4;
+/// Board status packed into one word, see `status_bits`
+pub const STATUS_CMD: u8 = 0xA5;
+/// IR3595 faults active at the last sample
+pub const FAULTS_CMD: u8 = 0xA6;
+/// IR3595 faults seen since boot or since the last `CLEAR_FAULTS_CMD`
+pub const LATCHED_FAULTS_CMD: u8 = 0xA7;
+/// Uptime in ms of the last sample
+pub const UPTIME_CMD: u8 = 0xA8;
+/// Number of power ups, kept in EEPROM
+pub const BOOT_COUNT_CMD: u8 = 0xA9;
+/// Number of proxied IR3595 reads that did not get through on I2C0 and were answered with 0xFF
+pub const FAILED_IR3595_READS_CMD: u8 = 0xAA;
+/// Epower register with the result of the last settings write: error code and the register of
+/// the first rejected setting, both 0 when the write was accepted
+pub const SETTINGS_ERROR_CMD: u8 = 0xAB;
+
+pub mod status_bits {
+    pub const L1_ENABLED: u16 = 1 << 0;
+    pub const L2_ENABLED: u16 = 1 << 1;
+    pub const L1_POWER_GOOD: u16 = 1 << 2;
+    pub const L2_POWER_GOOD: u16 = 1 << 3;
+    pub const FAULT_ACTIVE: u16 = 1 << 4;
+    pub const FAULT_LATCHED: u16 = 1 << 5;
+    pub const PROXY_LOCKED: u16 = 1 << 6;
+}
+
+/// Command byte followed by a whole settings export, which is more than the settings range, plus
+/// PEC
+pub const RECIEVE_BUFFER_SIZE: usize = EXPORT_SIZE + 2;
+
+/// Index of the 12V input rail in `Monitoring::adc_mv`
+const RAIL_12V: usize = 2;
+
+const PEC: crc::Crc<u8> = crc::Crc::<u8>::new(&crc::CRC_8_SMBUS);
+
+fn pec(header: &[u8], data: &[u8]) -> u8 {
+    //! `header` holds the address bytes and the command code as they appeared on the bus
+    let mut digest = PEC.digest();
+    digest.update(header);
+    digest.update(data);
+    digest.finalize()
+}
+
+fn read_pec(addr: u8, cmd: u8, data: &[u8]) -> u8 {
+    pec(&[addr << 1, cmd, (addr << 1) | 1], data)
+}
+
+/// Recordings the board streams byte by byte
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stream {
+    /// All recorded sweep steps back to back
+    SweepRecords,
+    /// Telemetry samples from the oldest on
+    History,
+    /// Proxied IR3595 writes from the oldest on
+    Journal,
+}
+
+/// Source for reads that keep sending data until the master NACKs
+enum ReadStream {
+    Board(Stream),
+    /// PMBus block read, the byte count is sent first
+    Block(&'static [u8]),
+    /// Settings as they were when the read started
+    Export([u8; EXPORT_SIZE]),
+    /// Name of a preset, padded with 0
+    PresetName([u8; PRESET_NAME_SIZE]),
+    /// Read without a command byte before it
+    Filler,
+}
+
+/// Bytes of a single register in the order they are sent
+#[derive(Debug, Copy, Clone)]
+struct RegisterValue {
+    bytes: [u8; 4],
+    len: usize,
+}
+
+impl RegisterValue {
+    /// Sent for commands that are not mapped to a register
+    const UNMAPPED: RegisterValue = RegisterValue::u8(FILLER_BYTE);
+
+    const fn u8(value: u8) -> Self {
+        RegisterValue {
+            bytes: [value, 0, 0, 0],
+            len: 1,
+        }
+    }
+
+    const fn u16(value: u16) -> Self {
+        let [higher, lower] = value.to_be_bytes();
+        RegisterValue {
+            bytes: [higher, lower, 0, 0],
+            len: 2,
+        }
+    }
+
+    const fn u16_le(value: u16) -> Self {
+        //! PMBus words are sent LSB first
+        let [lower, higher] = value.to_le_bytes();
+        RegisterValue {
+            bytes: [lower, higher, 0, 0],
+            len: 2,
+        }
+    }
+
+    const fn u32(value: u32) -> Self {
+        RegisterValue {
+            bytes: value.to_be_bytes(),
+            len: 4,
+        }
+    }
+}
+
+/// Permission of a proxied IR3595 command, ordered from least to most restrictive
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProxyAccess {
+    ReadWrite,
+    ReadOnly,
+    Hidden,
+}
+
+impl From<u8> for ProxyAccess {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ProxyAccess::ReadWrite,
+            1 => ProxyAccess::ReadOnly,
+            _ => ProxyAccess::Hidden,
+        }
+    }
+}
+
+/// What became of a proxied IR3595 write
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum WriteResult {
+    /// Sent to the IR3595 and acknowledged
+    #[default]
+    Forwarded,
+    /// Sent to the IR3595 but not acknowledged, or I2C0 was not usable
+    NotAcknowledged,
+    /// Refused by the proxy access rules
+    Denied,
+    /// Rejected because of a bad PEC, the data includes the received PEC byte
+    PecError,
+}
+
+impl From<WriteResult> for u8 {
+    fn from(result: WriteResult) -> u8 {
+        match result {
+            WriteResult::Forwarded => 0,
+            WriteResult::NotAcknowledged => 1,
+            WriteResult::Denied => 2,
+            WriteResult::PecError => 3,
+        }
+    }
+}
+
+/// Outcome of a proxied read, the EVBot master is kept waiting until there is one
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReadEvent {
+    Byte(u8),
+    /// The IR3595 did not answer or I2C0 did not get through
+    Failed,
+}
+
+/// Telemetry of one loop at the last sample
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LoopMonitoring {
+    pub enabled: bool,
+    pub power_good: bool,
+    pub voltage_mv: f32,
+    pub current_a: u16,
+    pub power_mw: u32,
+    pub temp_c: u8,
+    /// Energy delivered since the last `ENERGY_RESET_CMD`
+    pub energy_mwh: u32,
+    pub average_power_w: u16,
+}
+
+/// Telemetry of the last sample, as far as the registers present it
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Monitoring {
+    pub timestamp_ms: u32,
+    /// L1 output, L2 output, 12V, 5V and 3V
+    pub adc_mv: [u16; 5],
+    /// Active IR3595 faults
+    pub faults: u16,
+    pub loops: [LoopMonitoring; 2],
+    /// Time covered by the energy accounting
+    pub session_duration_s: u32,
+}
+
+/// Everything outside of the registers, implemented by the firmware for each interrupt.
+///
+/// Values written by the host are checked before they are passed on, so the board can take them
+/// as they are.
+pub trait Board {
+    /// Current value of a valid setting, including changes kept back by the manual commit
+    fn setting(&self, setting_id: u8) -> u8;
+    /// Writes consecutive settings and applies them
+    fn write_settings(&mut self, first_setting_id: u8, values: &[u8]);
+    fn has_uncommitted_settings(&self) -> bool;
+    fn commit_settings(&mut self);
+    fn discard_settings(&mut self);
+    fn restore_default_settings(&mut self);
+    /// The committed settings and the preset names, see `settings::export`
+    fn export_settings(&self) -> [u8; EXPORT_SIZE];
+    /// Replaces all settings and preset names and stores them without a commit
+    fn import_settings(&mut self, settings: &[u8; 60], names: &[u8; PRESET_NAMES_SIZE]);
+    /// Print the exported settings over UART
+    fn print_settings_export(&mut self);
+    fn preset_name(&self, number: u8) -> [u8; PRESET_NAME_SIZE];
+    /// `name` is padded with 0, an empty name removes it
+    fn set_preset_name(&mut self, number: u8, name: &[u8]);
+    fn active_preset(&self) -> u8;
+    /// `number` is at most `settings::PRESET_COUNT`
+    fn select_preset(&mut self, number: u8);
+    fn boot_count(&self) -> u32;
+    /// 7 bit address the Epower answers to, `None` if it is disabled
+    fn epower_address(&self) -> Option<u8>;
+    /// 7 bit address the IR3595 proxy answers to, `None` if it is disabled
+    fn proxy_address(&self) -> Option<u8>;
+
+    fn monitoring(&self) -> Monitoring;
+    /// Whether the loop (0 is L1) is enabled right now, not at the last sample
+    fn is_loop_enabled(&self, index: usize) -> bool;
+    fn reset_energy(&mut self);
+
+    /// Loop, start VID, stop VID, VID step and dwell time as written to `SWEEP_CONFIG_CMD`
+    fn configure_sweep(&mut self, config: &[u8]);
+    fn start_sweep(&mut self);
+    fn abort_sweep(&mut self);
+    fn sweep_state(&self) -> u8;
+    fn sweep_record_count(&self) -> u8;
+
+    fn history_len(&self) -> u16;
+    fn is_history_paused(&self) -> bool;
+    fn set_history_paused(&mut self, paused: bool);
+    fn clear_history(&mut self);
+    fn journal_len(&self) -> u16;
+    fn is_journal_paused(&self) -> bool;
+    fn set_journal_paused(&mut self, paused: bool);
+    fn clear_journal(&mut self);
+    /// Print the journal over UART
+    fn request_journal_dump(&mut self);
+    /// Byte `index` of a recording read out from entry `start` on, `None` behind its end
+    fn stream_byte(&self, stream: Stream, start: usize, index: usize) -> Option<u8>;
+
+    fn update_state(&self) -> u8;
+    fn next_update_block(&self) -> u16;
+    fn start_update(&mut self, length: u32, crc: u32);
+    fn write_update_block(&mut self, block: u16, data: &[u8]);
+    fn install_update(&mut self);
+    /// Left to the main loop, which stores pending settings first
+    fn reset(&mut self);
+    /// Left to the main loop, which stores pending settings first
+    fn enter_isp(&mut self);
+
+    /// Lets proxied transfers still on I2C0 finish before the Epower itself uses it
+    fn settle_proxy(&mut self);
+    /// Holds back a lone IR3595 command byte, it might be the write part of a combined read
+    fn set_pending_ir3595_cmd(&mut self, cmd: u8, writable: bool);
+    fn pending_ir3595_cmd(&self) -> Option<u8>;
+    fn discard_pending_ir3595_cmd(&mut self);
+    /// Forwards the write without waiting for it, the board records its result once it is done
+    fn write_ir3595(&mut self, data: &[u8]);
+    /// The byte is passed to `EvbotState::ir3595_read_done` once it is there, `single` stops
+    /// after one byte
+    fn start_ir3595_read(&mut self, single: bool);
+    /// Requests the next byte like `start_ir3595_read`, false if the read cannot go on
+    fn next_ir3595_read(&mut self) -> bool;
+    fn finish_ir3595_read(&mut self);
+    fn record_ir3595_write(&mut self, write: &[u8], result: WriteResult);
+}
+
+/// State of the EVBot registers between transactions
+pub struct EvbotState {
+    /// Epower command written without data, read by the next read
+    read_cmd: Option<u8>,
+    /// 7 bit address the current transaction was addressed to
+    slave_addr: u8,
+    read_register: u8,
+    read_value: RegisterValue,
+    read_index: usize,
+    stream: Option<ReadStream>,
+    stream_index: usize,
+    history_start: usize,
+    journal_start: usize,
+    pmbus_page: u8,
+    pmbus_cml: u8,
+    /// PEC setting latched at the start of each transaction
+    pec: bool,
+    read_pec: u8,
+    /// Result of the last write to the settings or the preset names
+    last_error: Option<SettingError>,
+    epower_pec_errors: u16,
+    ir3595_pec_errors: u16,
+    bus_errors: u16,
+    denied_ir3595_accesses: u16,
+    failed_ir3595_reads: u16,
+    bus_recoveries: u16,
+    latched_faults: u16,
+    firmware_version: u8,
+    uid: u32,
+}
+
+impl EvbotState {
+    pub const fn new(firmware_version: u8, uid: u32) -> Self {
+        EvbotState {
+            read_cmd: None,
+            slave_addr: 0,
+            read_register: 0,
+            read_value: RegisterValue::UNMAPPED,
+            read_index: 0,
+            stream: None,
+            stream_index: 0,
+            history_start: 0,
+            journal_start: 0,
+            pmbus_page: 0,
+            pmbus_cml: 0,
+            pec: false,
+            read_pec: 0,
+            last_error: None,
+            epower_pec_errors: 0,
+            ir3595_pec_errors: 0,
+            bus_errors: 0,
+            denied_ir3595_accesses: 0,
+            failed_ir3595_reads: 0,
+            bus_recoveries: 0,
+            latched_faults: 0,
+            firmware_version,
+            uid,
+        }
+    }
+
+    pub fn record_sample(&mut self, faults: u16, bus_recoveries: u16) {
+        //! Keeps the status registers up to date with every telemetry sample
+        self.latched_faults |= faults;
+        self.bus_recoveries = bus_recoveries;
+    }
+
+    pub fn ir3595_read_done(&mut self, cmd: Option<u8>, event: ReadEvent) -> u8 {
+        //! The byte a proxied read sends, `cmd` is the command the read started at.
+        //!
+        //! A failed read sends 0xFF, with PEC enabled followed by a PEC that does not match.
+        let value = match event {
+            ReadEvent::Byte(value) => value,
+            ReadEvent::Failed => {
+                self.failed_ir3595_reads = self.failed_ir3595_reads.saturating_add(1);
+                0xFF
+            }
+        };
+        self.read_pec = read_pec(self.slave_addr, cmd.unwrap_or_default(), &[value]);
+        if event == ReadEvent::Failed {
+            self.read_pec = !self.read_pec;
+        }
+        value
+    }
+
+    fn constants_value(&self, cmd: u8) -> RegisterValue {
+        match cmd {
+            1 => RegisterValue::u8(self.firmware_version),
+            // the UID has always been sent LSB first
+            2 => RegisterValue {
+                bytes: self.uid.to_le_bytes(),
+                len: 4,
+            },
+            _ => RegisterValue::UNMAPPED,
+        }
+    }
+
+    fn error_register(error: SettingError) -> u8 {
+        //! The register of the rejected value, 0 for a damaged import
+        match error {
+            SettingError::InvalidName(number) => PRESET_NAMES_CMD + number - 1,
+            error => error.setting_id().map_or(0, |setting_id| setting_id + 64),
+        }
+    }
+
+    fn strip_write_pec<'a>(&self, write: &'a [u8]) -> Option<&'a [u8]> {
+        //! Strips a valid PEC from the received bytes, a lone command code carries no PEC
+        match write.split_last() {
+            Some((&received, data)) if self.pec && !data.is_empty() => {
+                (pec(&[self.slave_addr << 1], data) == received).then_some(data)
+            }
+            _ => Some(write),
+        }
+    }
+}
+
+/// Connects the EVBot protocol to the registers and the board for one interrupt
+pub struct Evbot<'a, B: Board> {
+    state: &'a mut EvbotState,
+    board: &'a mut B,
+}
+
+impl<'a, B: Board> Evbot<'a, B> {
+    pub fn new(state: &'a mut EvbotState, board: &'a mut B) -> Self {
+        Evbot { state, board }
+    }
+
+    fn setting(&self, setting_id: usize) -> u8 {
+        self.board.setting(setting_id as u8)
+    }
+
+    fn is_pmbus_personality_enabled(&self) -> bool {
+        self.setting(PMBUS_PERSONALITY_OFFSET) == 1
+    }
+
+    fn proxy_access(&self, cmd: Option<u8>) -> ProxyAccess {
+        //! The most restrictive matching rule wins, the lock makes everything at least read-only.
+        //!
+        //! Reads without a command byte continue wherever the IR3595 left off, so every rule
+        //! applies. Unused rules have permission 0 and allow what is allowed anyway.
+        let base = if self.setting(PROXY_LOCK_OFFSET) == 1 {
+            ProxyAccess::ReadOnly
+        } else {
+            ProxyAccess::ReadWrite
+        };
+        PROXY_ACCESS_RULES_RANGE
+            .step_by(PROXY_ACCESS_RULE_SIZE)
+            .map(|rule| (self.setting(rule), self.setting(rule + 1)))
+            .filter(|&(register, _)| cmd.is_none_or(|cmd| cmd == register))
+            .map(|(_, access)| ProxyAccess::from(access))
+            .fold(base, Ord::max)
+    }
+
+    fn monitoring_value(cmd: u8, monitoring: &Monitoring) -> RegisterValue {
+        let [l1, l2] = &monitoring.loops;
+        match cmd {
+            0..=4 => RegisterValue::u16(monitoring.adc_mv[cmd as usize]),
+            16 => RegisterValue::u32(l1.energy_mwh),
+            17 => RegisterValue::u16(l1.average_power_w),
+            18 => RegisterValue::u32(l2.energy_mwh),
+            19 => RegisterValue::u16(l2.average_power_w),
+            20 => RegisterValue::u32(monitoring.session_duration_s),
+            _ => RegisterValue::UNMAPPED,
+        }
+    }
+
+    fn register_value(&self, cmd: u8) -> RegisterValue {
+        //! Streamed commands are only readable when addressed directly
+        let state = &*self.state;
+        let board = &*self.board;
+        match cmd {
+            0..=63 => state.constants_value(cmd),
+            64..=127 => {
+                let setting_id = cmd - 64;
+                if settings::is_valid_setting(setting_id as usize) {
+                    RegisterValue::u8(board.setting(setting_id))
+                } else {
+                    RegisterValue::u8(0)
+                }
+            }
+            PRESET_SELECT_CMD => RegisterValue::u8(board.active_preset()),
+            COMMIT_SETTINGS_CMD => RegisterValue::u8(board.has_uncommitted_settings() as u8),
+            UPDATE_STATUS_CMD => {
+                let [higher, lower] = board.next_update_block().to_be_bytes();
+                RegisterValue {
+                    bytes: [board.update_state(), higher, lower, 0],
+                    len: 3,
+                }
+            }
+            SWEEP_CONTROL_CMD => RegisterValue::u8(board.sweep_state()),
+            SWEEP_COUNT_CMD => RegisterValue::u8(board.sweep_record_count()),
+            HISTORY_COUNT_CMD => RegisterValue::u16(board.history_len()),
+            HISTORY_CONTROL_CMD => RegisterValue::u8(board.is_history_paused() as u8),
+            JOURNAL_COUNT_CMD => RegisterValue::u16(board.journal_len()),
+            JOURNAL_CONTROL_CMD => RegisterValue::u8(board.is_journal_paused() as u8),
+            SWEEP_RESULTS_CMD
+            | HISTORY_DATA_CMD
+            | JOURNAL_DATA_CMD
+            | SETTINGS_EXPORT_CMD
+            | PRESET_NAMES_CMD..=LAST_PRESET_NAME_CMD => RegisterValue::UNMAPPED,
+            EPOWER_PEC_ERRORS_CMD => RegisterValue::u16(state.epower_pec_errors),
+            IR3595_PEC_ERRORS_CMD => RegisterValue::u16(state.ir3595_pec_errors),
+            BUS_ERRORS_CMD => RegisterValue::u16(state.bus_errors),
+            DENIED_IR3595_ACCESSES_CMD => RegisterValue::u16(state.denied_ir3595_accesses),
+            BUS_RECOVERIES_CMD => RegisterValue::u16(state.bus_recoveries),
+            STATUS_CMD => RegisterValue::u16(self.status_word(&board.monitoring())),
+            FAULTS_CMD => RegisterValue::u16(board.monitoring().faults),
+            LATCHED_FAULTS_CMD => RegisterValue::u16(state.latched_faults),
+            UPTIME_CMD => RegisterValue::u32(board.monitoring().timestamp_ms),
+            BOOT_COUNT_CMD => RegisterValue::u32(board.boot_count()),
+            FAILED_IR3595_READS_CMD => RegisterValue::u16(state.failed_ir3595_reads),
+            SETTINGS_ERROR_CMD => match state.last_error {
+                Some(error) => RegisterValue::u16(u16::from_be_bytes([
+                    error.into(),
+                    EvbotState::error_register(error),
+                ])),
+                None => RegisterValue::u16(0),
+            },
+            128..=250 => Self::monitoring_value(cmd - 128, &board.monitoring()),
+            251..=255 => RegisterValue::UNMAPPED,
+        }
+    }
+
+    fn status_word(&self, monitoring: &Monitoring) -> u16 {
+        let [l1, l2] = &monitoring.loops;
+        let bits = [
+            (l1.enabled, status_bits::L1_ENABLED),
+            (l2.enabled, status_bits::L2_ENABLED),
+            (l1.power_good, status_bits::L1_POWER_GOOD),
+            (l2.power_good, status_bits::L2_POWER_GOOD),
+            (monitoring.faults != 0, status_bits::FAULT_ACTIVE),
+            (self.state.latched_faults != 0, status_bits::FAULT_LATCHED),
+            (
+                self.setting(PROXY_LOCK_OFFSET) == 1,
+                status_bits::PROXY_LOCKED,
+            ),
+        ];
+        bits.iter()
+            .filter(|(set, _)| *set)
+            .fold(0, |word, (_, bit)| word | bit)
+    }
+
+    fn start_stream(&mut self, stream: ReadStream) -> u8 {
+        self.state.stream = Some(stream);
+        self.state.stream_index = 0;
+        self.stream_byte()
+    }
+
+    fn stream_byte(&self) -> u8 {
+        let state = &*self.state;
+        let index = state.stream_index;
+        match state.stream {
+            Some(ReadStream::Board(stream)) => {
+                let start = match stream {
+                    Stream::SweepRecords => 0,
+                    Stream::History => state.history_start,
+                    Stream::Journal => state.journal_start,
+                };
+                self.board.stream_byte(stream, start, index)
+            }
+            Some(ReadStream::Block(data)) => match index {
+                0 => Some(data.len() as u8),
+                index if index == data.len() + 1 && state.pec => Some(state.read_pec),
+                index => data.get(index - 1).copied(),
+            },
+            Some(ReadStream::Export(export)) => export.get(index).copied(),
+            Some(ReadStream::PresetName(name)) => name.get(index).copied(),
+            Some(ReadStream::Filler) | None => None,
+        }
+        .unwrap_or(FILLER_BYTE)
+    }
+
+    fn pmbus_status_word(&self) -> u16 {
+        let mut status = 0;
+        if !self.board.is_loop_enabled(self.state.pmbus_page as usize) {
+            status |= status_word::OFF | status_word::POWER_GOOD_N;
+        }
+        if self.state.pmbus_cml != 0 {
+            status |= status_word::CML;
+        }
+        if status & 0xFF00 != 0 {
+            status |= status_word::NONE_OF_THE_ABOVE;
+        }
+        status
+    }
+
+    fn pmbus_value(&mut self, cmd: u8) -> RegisterValue {
+        let monitoring = self.board.monitoring();
+        let output = &monitoring.loops[self.state.pmbus_page as usize];
+        match cmd {
+            commands::PAGE => RegisterValue::u8(self.state.pmbus_page),
+            commands::VOUT_MODE => RegisterValue::u8(PMBUS_VOUT_EXPONENT as u8 & 0x1F),
+            commands::STATUS_BYTE => RegisterValue::u8(self.pmbus_status_word() as u8),
+            commands::STATUS_WORD => RegisterValue::u16_le(self.pmbus_status_word()),
+            commands::STATUS_CML => RegisterValue::u8(self.state.pmbus_cml),
+            commands::PMBUS_REVISION => RegisterValue::u8(PMBUS_REVISION),
+            PMBUS_PERSONALITY_CMD => RegisterValue::u8(1),
+            commands::READ_VIN => {
+                let vin = monitoring.adc_mv[RAIL_12V] as f32 / 1000.0;
+                RegisterValue::u16_le(pmbus::f32_to_linear11(vin))
+            }
+            commands::READ_VOUT => {
+                let vout = output.voltage_mv / 1000.0;
+                RegisterValue::u16_le(pmbus::f32_to_linear16(vout, PMBUS_VOUT_EXPONENT))
+            }
+            commands::READ_IOUT => {
+                RegisterValue::u16_le(pmbus::f32_to_linear11(output.current_a as f32))
+            }
+            commands::READ_POUT => {
+                let pout = output.power_mw as f32 / 1000.0;
+                RegisterValue::u16_le(pmbus::f32_to_linear11(pout))
+            }
+            commands::READ_TEMPERATURE_1 => {
+                RegisterValue::u16_le(pmbus::f32_to_linear11(output.temp_c as f32))
+            }
+            commands::MFR_ID | commands::MFR_MODEL => {
+                let data = if cmd == commands::MFR_ID {
+                    PMBUS_MFR_ID
+                } else {
+                    PMBUS_MFR_MODEL
+                };
+                self.state.stream = Some(ReadStream::Block(data));
+                self.state.stream_index = 0;
+                let len = data.len() as u8;
+                let addr_shifted = self.state.slave_addr << 1;
+                let header = [addr_shifted, cmd, addr_shifted | 1, len];
+                self.state.read_pec = pec(&header, data);
+                RegisterValue::u8(len)
+            }
+            _ => {
+                self.state.pmbus_cml |= status_cml::INVALID_COMMAND;
+                RegisterValue::u8(0xFF)
+            }
+        }
+    }
+
+    fn handle_pmbus_write(&mut self, write: &[u8]) {
+        let (cmd, value) = match *write {
+            [] => return,
+            // only the command code, either a send byte or the write part of a read
+            [cmd] => {
+                if cmd == commands::CLEAR_FAULTS {
+                    self.state.pmbus_cml = 0;
+                }
+                return;
+            }
+            [cmd, value, ..] => (cmd, value),
+        };
+        match cmd {
+            commands::PAGE => {
+                if value < PMBUS_PAGE_COUNT {
+                    self.state.pmbus_page = value;
+                } else {
+                    self.state.pmbus_cml |= status_cml::INVALID_DATA;
+                }
+            }
+            PMBUS_PERSONALITY_CMD => {
+                let enabled = (value != 0) as u8;
+                self.board
+                    .write_settings(PMBUS_PERSONALITY_OFFSET as u8, &[enabled]);
+            }
+            _ => {
+                self.state.pmbus_cml |= status_cml::INVALID_COMMAND;
+            }
+        }
+    }
+
+    fn report(&mut self, result: Result<(), SettingError>) -> bool {
+        //! Keeps the outcome of a settings write for `SETTINGS_ERROR_CMD`, true if it was accepted
+        self.state.last_error = result.err();
+        self.state.last_error.is_none()
+    }
+
+    fn handle_epower_write(&mut self, write: &[u8]) {
+        //! `write` holds the command byte and at least one data byte
+        let cmd = write[0];
+        let value = write[1];
+        let data = &write[1..];
+        match cmd {
+            64..=127 => {
+                // consecutive bytes go to consecutive settings, none is written if any is rejected
+                let setting_id = cmd - 64;
+                if self.report(settings::check_values(setting_id, data)) {
+                    self.board.write_settings(setting_id, data);
+                }
+            }
+            PRESET_SELECT_CMD if value <= PRESET_COUNT => self.board.select_preset(value),
+            PRESET_NAMES_CMD..=LAST_PRESET_NAME_CMD => {
+                let number = cmd - PRESET_NAMES_CMD + 1;
+                if self.report(settings::check_name(number, data)) {
+                    self.board.set_preset_name(number, data);
+                }
+            }
+            SWEEP_CONFIG_CMD => self.board.configure_sweep(data),
+            SWEEP_CONTROL_CMD => {
+                if value == 1 {
+                    self.board.start_sweep();
+                } else {
+                    self.board.abort_sweep();
+                }
+            }
+            ENERGY_RESET_CMD => self.board.reset_energy(),
+            CLEAR_FAULTS_CMD => self.state.latched_faults = 0,
+            SETTINGS_EXPORT_CMD if value == 1 => self.board.print_settings_export(),
+            SETTINGS_IMPORT_CMD => match settings::parse_export(data) {
+                Ok((settings, names)) => {
+                    self.state.last_error = None;
+                    self.board.import_settings(&settings, &names);
+                }
+                Err(error) => self.state.last_error = Some(error),
+            },
+            // confirmation key of the commands that cannot be undone: the complement of the command
+            COMMIT_SETTINGS_CMD..=ENTER_ISP_CMD | UPDATE_INSTALL_CMD if value != !cmd => {}
+            COMMIT_SETTINGS_CMD => self.board.commit_settings(),
+            DISCARD_SETTINGS_CMD => self.board.discard_settings(),
+            RESTORE_DEFAULTS_CMD => self.board.restore_default_settings(),
+            SOFT_RESET_CMD => self.board.reset(),
+            ENTER_ISP_CMD => self.board.enter_isp(),
+            UPDATE_START_CMD => {
+                if let [l0, l1, l2, l3, c0, c1, c2, c3, ..] = *data {
+                    let length = u32::from_be_bytes([l0, l1, l2, l3]);
+                    let crc = u32::from_be_bytes([c0, c1, c2, c3]);
+                    self.board.start_update(length, crc);
+                }
+            }
+            UPDATE_DATA_CMD => {
+                if let [higher, lower, ref image @ ..] = *data {
+                    let block = u16::from_be_bytes([higher, lower]);
+                    self.board.write_update_block(block, image);
+                }
+            }
+            UPDATE_INSTALL_CMD => self.board.install_update(),
+            HISTORY_DATA_CMD => {
+                if let [higher, lower, ..] = *data {
+                    self.state.history_start = u16::from_be_bytes([higher, lower]) as usize;
+                }
+            }
+            HISTORY_CONTROL_CMD => match value {
+                0 => self.board.set_history_paused(false),
+                1 => self.board.set_history_paused(true),
+                2 => self.board.clear_history(),
+                _ => {}
+            },
+            JOURNAL_DATA_CMD => {
+                if let [higher, lower, ..] = *data {
+                    self.state.journal_start = u16::from_be_bytes([higher, lower]) as usize;
+                }
+            }
+            JOURNAL_CONTROL_CMD => match value {
+                0 => self.board.set_journal_paused(false),
+                1 => self.board.set_journal_paused(true),
+                2 => self.board.clear_journal(),
+                3 => self.board.request_journal_dump(),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn start_epower_read(&mut self, cmd: u8) -> u8 {
+        self.state.read_register = cmd;
+        self.state.read_index = 0;
+        self.state.read_value = if self.is_pmbus_personality_enabled() {
+            self.pmbus_value(cmd)
+        } else {
+            match cmd {
+                SWEEP_RESULTS_CMD => {
+                    return self.start_stream(ReadStream::Board(Stream::SweepRecords))
+                }
+                HISTORY_DATA_CMD => return self.start_stream(ReadStream::Board(Stream::History)),
+                JOURNAL_DATA_CMD => return self.start_stream(ReadStream::Board(Stream::Journal)),
+                SETTINGS_EXPORT_CMD => {
+                    let export = ReadStream::Export(self.board.export_settings());
+                    return self.start_stream(export);
+                }
+                PRESET_NAMES_CMD..=LAST_PRESET_NAME_CMD => {
+                    let number = cmd - PRESET_NAMES_CMD + 1;
+                    let name = ReadStream::PresetName(self.board.preset_name(number));
+                    return self.start_stream(name);
+                }
+                _ => self.register_value(cmd),
+            }
+        };
+        // block reads already computed the PEC over the whole block
+        if self.state.stream.is_none() {
+            let value = self.state.read_value;
+            self.state.read_pec = read_pec(self.state.slave_addr, cmd, &value.bytes[..value.len]);
+        }
+        self.state.read_value.bytes[0]
+    }
+
+    fn next_epower_read_byte(&mut self) -> u8 {
+        //! Once all bytes of a register are sent the read continues with the next register,
+        //! unless PEC is enabled. Then the register is followed by its PEC instead.
+        if self.state.stream.is_some() {
+            self.state.stream_index += 1;
+            return self.stream_byte();
+        }
+        let state = &mut *self.state;
+        state.read_index += 1;
+        if state.read_index < state.read_value.len {
+            return state.read_value.bytes[state.read_index];
+        }
+        if state.pec && state.read_index == state.read_value.len {
+            return state.read_pec;
+        }
+        if state.pec || self.is_pmbus_personality_enabled() {
+            return 0xFF;
+        }
+        self.state.read_register = self.state.read_register.saturating_add(1);
+        self.state.read_value = self.register_value(self.state.read_register);
+        self.state.read_index = 0;
+        self.state.read_value.bytes[0]
+    }
+
+    fn handle_ir3595_write(&mut self, received: &[u8]) {
+        let Some(write) = self.state.strip_write_pec(received) else {
+            self.state.ir3595_pec_errors = self.state.ir3595_pec_errors.saturating_add(1);
+            self.board
+                .record_ir3595_write(received, WriteResult::PecError);
+            return;
+        };
+        let Some(&cmd) = write.first() else {
+            return;
+        };
+        let writable = self.proxy_access(Some(cmd)) == ProxyAccess::ReadWrite;
+        if write.len() == 1 {
+            // might be the write part of a combined read, see `Board::set_pending_ir3595_cmd`
+            self.board.set_pending_ir3595_cmd(cmd, writable);
+        } else if writable {
+            self.board.write_ir3595(write);
+        } else {
+            self.state.denied_ir3595_accesses = self.state.denied_ir3595_accesses.saturating_add(1);
+            self.board.record_ir3595_write(write, WriteResult::Denied);
+        }
+    }
+
+    fn start_ir3595_read(&mut self) -> Option<u8> {
+        //! `None` while the byte is read from the IR3595, see `EvbotState::ir3595_read_done`
+        let hidden = self.proxy_access(self.board.pending_ir3595_cmd()) == ProxyAccess::Hidden;
+        if hidden {
+            self.board.discard_pending_ir3595_cmd();
+            self.state.denied_ir3595_accesses = self.state.denied_ir3595_accesses.saturating_add(1);
+            // skip the PEC byte, the host sees a PEC error instead of a value
+            self.state.read_index = 1;
+            return Some(0xFF);
+        }
+        self.state.read_index = 0;
+        // the PEC covers the command, so a read with PEC needs one
+        if self.state.pec && self.board.pending_ir3595_cmd().is_none() {
+            return Some(self.state.ir3595_read_done(None, ReadEvent::Failed));
+        }
+        self.board.start_ir3595_read(self.state.pec);
+        None
+    }
+
+    fn next_ir3595_read_byte(&mut self) -> Option<u8> {
+        if !self.state.pec {
+            return if self.board.next_ir3595_read() {
+                None
+            } else {
+                Some(0xFF)
+            };
+        }
+        // only single byte reads are proxied with PEC
+        self.state.read_index += 1;
+        if self.state.read_index == 1 {
+            Some(self.state.read_pec)
+        } else {
+            Some(0xFF)
+        }
+    }
+}
+
+impl<B: Board> Handler for Evbot<'_, B> {
+    fn select(&mut self, addr: u8) -> Option<Device> {
+        self.state.slave_addr = addr;
+        self.state.pec = self.setting(PEC_OFFSET) == 1;
+        let addr = Some(addr);
+        if addr == self.board.epower_address() {
+            Some(Device::Epower)
+        } else if addr == self.board.proxy_address() {
+            Some(Device::Ir3595)
+        } else {
+            None
+        }
+    }
+
+    fn write(&mut self, device: Device, data: &[u8]) {
+        if device == Device::Ir3595 {
+            self.handle_ir3595_write(data);
+            return;
+        }
+        self.board.settle_proxy();
+        let Some(write) = self.state.strip_write_pec(data) else {
+            self.state.epower_pec_errors = self.state.epower_pec_errors.saturating_add(1);
+            if self.is_pmbus_personality_enabled() {
+                self.state.pmbus_cml |= status_cml::PEC_FAILED;
+            }
+            self.state.read_cmd = None;
+            return;
+        };
+        // a lone command code selects the register of the next read
+        self.state.read_cmd = match *write {
+            [cmd] => Some(cmd),
+            _ => None,
+        };
+        if self.is_pmbus_personality_enabled() {
+            self.handle_pmbus_write(write);
+        } else if write.len() >= 2 {
+            self.handle_epower_write(write);
+        }
+    }
+
+    fn read_start(&mut self, device: Device) -> Option<u8> {
+        match device {
+            Device::Epower => Some(match self.state.read_cmd.take() {
+                Some(cmd) => self.start_epower_read(cmd),
+                // not the register of the previous read
+                None => self.start_stream(ReadStream::Filler),
+            }),
+            Device::Ir3595 => self.start_ir3595_read(),
+        }
+    }
+
+    fn read_next(&mut self, device: Device) -> Option<u8> {
+        match device {
+            Device::Epower => Some(self.next_epower_read_byte()),
+            Device::Ir3595 => self.next_ir3595_read_byte(),
+        }
+    }
+
+    fn read_end(&mut self) {
+        self.board.finish_ir3595_read();
+        // a start index written to 0xD1 or 0xD4 only applies to the read that follows it
+        match self.state.stream.take() {
+            Some(ReadStream::Board(Stream::History)) => self.state.history_start = 0,
+            Some(ReadStream::Board(Stream::Journal)) => self.state.journal_start = 0,
+            _ => {}
+        }
+    }
+
+    fn bus_error(&mut self) {
+        self.state.bus_errors = self.state.bus_errors.saturating_add(1);
+        self.state.read_cmd = None;
+    }
+}
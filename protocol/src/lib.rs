@@ -0,0 +1,272 @@
+//! I2C slave protocol of the EVBot connector.
+//!
+//! `SlaveMachine` is fed the status code and data byte of every I2C1 interrupt and returns what
+//! has to be done with the peripheral, the registers behind the two slave addresses are served by
+//! a `Handler`. `evbot::Evbot` is the handler of the firmware, it reaches the hardware through
+//! `evbot::Board`. None of them touches the hardware, so whole transactions can be replayed on
+//! the host.
+#![no_std]
+
+pub mod evbot;
+pub mod pmbus;
+pub mod settings;
+
+pub mod i2c_driver_states {
+    /// Bus error during MST or selected slave modes, due to an illegal START or STOP condition.
+    pub const BUS_ERROR: u32 = 0x00;
+
+    /// Own SLA+W has been received; ACK has been returned.
+    pub const SLAVE_WRITE_RECIEVED_AND_ACKED: u32 = 0x60;
+
+    // Arbitration lost in SLA+R/W as master; Own SLA+W has been received, ACK returned
+    pub const ARBITRATION_LOST_SLAVE_WRITE_RECIEVED: u32 = 0x68;
+
+    // General call address (0x00) has been received; ACK has been returned
+    pub const GENERAL_CALL_RECIEVED: u32 = 0x70;
+
+    // Arbitration lost in SLA+R/W as master; General call address has been received, ACK returned
+    pub const ARBITRATION_LOST_GENERAL_CALL_RECIEVED: u32 = 0x78;
+
+    // Previously addressed with own SLV address; DATA has been received; ACK has been returned
+    pub const RECIEVED_DATA_AND_ACKED: u32 = 0x80;
+
+    // Previously addressed with own SLA; DATA byte has been received; NOT ACK has been returned
+    pub const RECIEVED_DATA_AND_NOT_ACKED: u32 = 0x88;
+
+    // Previously addressed with General Call; DATA byte has been received; ACK has been returned
+    pub const GENERAL_CALL_DATA_AND_ACKED: u32 = 0x90;
+
+    // Previously addressed with General Call; DATA byte has been received; NOT ACK has been returned
+    pub const GENERAL_CALL_DATA_AND_NOT_ACKED: u32 = 0x98;
+
+    // A STOP condition or Repeated START condition has been received while still addressed as SLV/REC or SLV/TRX
+    pub const RECIEVED_STOP_OR_REPEAT: u32 = 0xA0;
+
+    // Own SLA+R has been received; ACK has been returned.
+    pub const SLAVE_READ_RECIEVED_AND_ACKED: u32 = 0xA8;
+
+    // Arbitration lost in SLA+R/W as master; Own SLA+R has been received, ACK has been returned
+    pub const ARBITRATION_LOST_SLAVE_READ_RECIEVED: u32 = 0xB0;
+
+    // Data byte in DAT has been transmitted; ACK has been received.
+    pub const SEND_DATA_AND_RECIEVED_ACK: u32 = 0xB8;
+
+    // Data byte in DAT has been transmitted; NOT ACK has been received.
+    pub const SEND_DATA_AND_RECIEVED_NOT_ACK: u32 = 0xC0;
+
+    // Last data byte in DAT has been transmitted (AA = 0); ACK has been received
+    pub const SEND_LAST_DATA_AND_RECIEVED_ACK: u32 = 0xC8;
+
+    // No relevant state information available; SI = 0
+    pub const NO_INFORMATION: u32 = 0xF8;
+}
+
+/// Sent when there is nothing to send
+pub const FILLER_BYTE: u8 = 0xEE;
+
+/// The two devices on the EVBot connector
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Device {
+    /// The Epower register map or its PMBus personality
+    Epower,
+    /// The IR3595, forwarded to I2C0
+    Ir3595,
+}
+
+/// Registers behind the slave addresses
+pub trait Handler {
+    /// Called with the 7 bit address of every transaction, `None` if no device answers to it
+    fn select(&mut self, addr: u8) -> Option<Device>;
+    /// All bytes of a write, called once the master sends a STOP or a repeated START
+    fn write(&mut self, device: Device, data: &[u8]);
+    /// First byte of a read, `None` stretches the clock until the byte is passed to
+    /// `Action::send` later on
+    fn read_start(&mut self, device: Device) -> Option<u8>;
+    /// Following bytes of a read, for as long as the master acknowledges
+    fn read_next(&mut self, device: Device) -> Option<u8>;
+    /// Called whenever a read ends, also when no read was in progress
+    fn read_end(&mut self);
+    fn bus_error(&mut self);
+}
+
+/// What to do with the peripheral after a status code was handled
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Action {
+    /// Byte to load into DAT
+    pub data: Option<u8>,
+    /// Set AA, so the next byte or the own address is acknowledged
+    pub ack: bool,
+    /// Set STO, which leaves the bus error state without sending a STOP
+    pub stop: bool,
+    /// Clear STA
+    pub clear_start: bool,
+    /// Clear SI, which lets the peripheral continue
+    pub clear_interrupt: bool,
+    /// SI stays set, so SCL is held low until the byte to send is ready
+    pub stretch: bool,
+}
+
+impl Action {
+    const NONE: Action = Action {
+        data: None,
+        ack: false,
+        stop: false,
+        clear_start: false,
+        clear_interrupt: false,
+        stretch: false,
+    };
+
+    const ACK: Action = Action {
+        ack: true,
+        clear_interrupt: true,
+        ..Action::NONE
+    };
+
+    pub const fn send(data: u8) -> Self {
+        Action {
+            data: Some(data),
+            ..Action::ACK
+        }
+    }
+
+    const fn send_when_ready(data: Option<u8>) -> Self {
+        match data {
+            Some(data) => Action::send(data),
+            None => Action {
+                stretch: true,
+                ..Action::NONE
+            },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    Idle,
+    Write(Device),
+    Read(Device),
+}
+
+/// Tracks the transactions on the EVBot connector, `N` is the longest write that is kept
+pub struct SlaveMachine<const N: usize> {
+    mode: Mode,
+    buffer: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> SlaveMachine<N> {
+    pub const fn new() -> Self {
+        SlaveMachine {
+            mode: Mode::Idle,
+            buffer: [0; N],
+            len: 0,
+        }
+    }
+
+    fn end_read(&mut self, handler: &mut impl Handler) {
+        self.mode = Mode::Idle;
+        self.len = 0;
+        handler.read_end();
+    }
+
+    pub fn step(&mut self, status: u32, data: u8, handler: &mut impl Handler) -> Action {
+        //! `data` is the content of DAT, it is only used for the states that received something
+        use i2c_driver_states::*;
+
+        match status {
+            // I2C1 never acts as master, so a lost arbitration is just a normal addressing
+            SLAVE_WRITE_RECIEVED_AND_ACKED | ARBITRATION_LOST_SLAVE_WRITE_RECIEVED => {
+                self.mode = handler.select(data >> 1).map_or(Mode::Idle, Mode::Write);
+                self.len = 0;
+                Action::ACK
+            }
+
+            RECIEVED_DATA_AND_ACKED => match self.mode {
+                Mode::Write(_) => {
+                    // longer writes are truncated
+                    if self.len < N {
+                        self.buffer[self.len] = data;
+                        self.len += 1;
+                    }
+                    Action::ACK
+                }
+                Mode::Idle | Mode::Read(_) => Action {
+                    clear_interrupt: true,
+                    ..Action::NONE
+                },
+            },
+
+            // general call is not enabled in ADR0/ADR1, ignore it should it ever be seen
+            GENERAL_CALL_RECIEVED
+            | ARBITRATION_LOST_GENERAL_CALL_RECIEVED
+            | GENERAL_CALL_DATA_AND_ACKED
+            | GENERAL_CALL_DATA_AND_NOT_ACKED => {
+                self.mode = Mode::Idle;
+                Action::ACK
+            }
+
+            // back in not addressed slave mode, AA is set again so the own address is recognized
+            RECIEVED_DATA_AND_NOT_ACKED
+            | SEND_LAST_DATA_AND_RECIEVED_ACK
+            | SEND_DATA_AND_RECIEVED_NOT_ACK => {
+                self.end_read(handler);
+                Action::ACK
+            }
+
+            RECIEVED_STOP_OR_REPEAT => {
+                match self.mode {
+                    Mode::Read(_) => self.end_read(handler),
+                    Mode::Write(device) => {
+                        handler.write(device, &self.buffer[..self.len]);
+                        self.len = 0;
+                    }
+                    Mode::Idle => {}
+                }
+                self.mode = Mode::Idle;
+                Action::ACK
+            }
+
+            SLAVE_READ_RECIEVED_AND_ACKED | ARBITRATION_LOST_SLAVE_READ_RECIEVED => {
+                match handler.select(data >> 1) {
+                    Some(device) => {
+                        self.mode = Mode::Read(device);
+                        Action::send_when_ready(handler.read_start(device))
+                    }
+                    None => {
+                        self.mode = Mode::Idle;
+                        Action::ACK
+                    }
+                }
+            }
+
+            SEND_DATA_AND_RECIEVED_ACK => match self.mode {
+                Mode::Read(device) => Action::send_when_ready(handler.read_next(device)),
+                Mode::Idle | Mode::Write(_) => Action::send(FILLER_BYTE),
+            },
+
+            BUS_ERROR => {
+                handler.bus_error();
+                self.end_read(handler);
+                // STO recovers from the bus error without sending a STOP on the bus
+                Action {
+                    stop: true,
+                    ..Action::ACK
+                }
+            }
+
+            NO_INFORMATION => Action::NONE,
+
+            // master states, I2C1 never starts a transfer itself
+            _ => Action {
+                clear_start: true,
+                ..Action::ACK
+            },
+        }
+    }
+}
+
+impl<const N: usize> Default for SlaveMachine<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,148 @@
+//! PMBus command codes, status bits and data formats, shared by the IR3595 driver and the PMBus
+//! personality of the Epower address.
+
+pub mod commands {
+    pub const PAGE: u8 = 0x00;
+    pub const OPERATION: u8 = 0x01;
+    pub const ON_OFF_CONFIG: u8 = 0x02;
+    pub const CLEAR_FAULTS: u8 = 0x03;
+    pub const WRITE_PROTECT: u8 = 0x10;
+    pub const CAPABILITY: u8 = 0x19;
+    pub const VOUT_MODE: u8 = 0x20;
+    pub const VOUT_COMMAND: u8 = 0x21;
+    pub const VOUT_TRIM: u8 = 0x22;
+    pub const VOUT_MAX: u8 = 0x24;
+    pub const VOUT_MARGIN_HIGH: u8 = 0x25;
+    pub const VOUT_MARGIN_LOW: u8 = 0x26;
+    pub const VOUT_OV_FAULT_LIMIT: u8 = 0x40;
+    pub const VOUT_UV_FAULT_LIMIT: u8 = 0x44;
+    pub const IOUT_OC_FAULT_LIMIT: u8 = 0x46;
+    pub const OT_FAULT_LIMIT: u8 = 0x4F;
+    pub const OT_WARN_LIMIT: u8 = 0x51;
+    pub const VIN_OV_FAULT_LIMIT: u8 = 0x55;
+    pub const VIN_UV_FAULT_LIMIT: u8 = 0x59;
+    pub const STATUS_BYTE: u8 = 0x78;
+    pub const STATUS_WORD: u8 = 0x79;
+    pub const STATUS_VOUT: u8 = 0x7A;
+    pub const STATUS_IOUT: u8 = 0x7B;
+    pub const STATUS_INPUT: u8 = 0x7C;
+    pub const STATUS_TEMPERATURE: u8 = 0x7D;
+    pub const STATUS_CML: u8 = 0x7E;
+    pub const READ_VIN: u8 = 0x88;
+    pub const READ_IIN: u8 = 0x89;
+    pub const READ_VOUT: u8 = 0x8B;
+    pub const READ_IOUT: u8 = 0x8C;
+    pub const READ_TEMPERATURE_1: u8 = 0x8D;
+    pub const READ_TEMPERATURE_2: u8 = 0x8E;
+    pub const READ_POUT: u8 = 0x96;
+    pub const READ_PIN: u8 = 0x97;
+    pub const PMBUS_REVISION: u8 = 0x98;
+    pub const MFR_ID: u8 = 0x99;
+    pub const MFR_MODEL: u8 = 0x9A;
+    pub const MFR_REVISION: u8 = 0x9B;
+}
+
+pub mod status_word {
+    pub const NONE_OF_THE_ABOVE: u16 = 1 << 0;
+    pub const CML: u16 = 1 << 1;
+    pub const TEMPERATURE: u16 = 1 << 2;
+    pub const VIN_UV: u16 = 1 << 3;
+    pub const IOUT_OC: u16 = 1 << 4;
+    pub const VOUT_OV: u16 = 1 << 5;
+    pub const OFF: u16 = 1 << 6;
+    pub const BUSY: u16 = 1 << 7;
+    pub const UNKNOWN: u16 = 1 << 8;
+    pub const OTHER: u16 = 1 << 9;
+    pub const FANS: u16 = 1 << 10;
+    pub const POWER_GOOD_N: u16 = 1 << 11;
+    pub const MFR: u16 = 1 << 12;
+    pub const INPUT: u16 = 1 << 13;
+    pub const IOUT_POUT: u16 = 1 << 14;
+    pub const VOUT: u16 = 1 << 15;
+}
+
+pub mod status_cml {
+    pub const INVALID_COMMAND: u8 = 1 << 7;
+    pub const INVALID_DATA: u8 = 1 << 6;
+    pub const PEC_FAILED: u8 = 1 << 5;
+    pub const MEMORY_FAULT: u8 = 1 << 4;
+    pub const PROCESSOR_FAULT: u8 = 1 << 3;
+    pub const OTHER_COMMUNICATION_FAULT: u8 = 1 << 1;
+    pub const OTHER_MEMORY_OR_LOGIC_FAULT: u8 = 1 << 0;
+}
+
+fn pow2(exponent: i8) -> f32 {
+    if exponent >= 0 {
+        (1u32 << exponent) as f32
+    } else {
+        1.0 / (1u32 << -exponent) as f32
+    }
+}
+
+fn round(value: f32) -> i32 {
+    if value < 0.0 {
+        (value - 0.5) as i32
+    } else {
+        (value + 0.5) as i32
+    }
+}
+
+pub fn linear11_to_f32(raw: u16) -> f32 {
+    //! LINEAR11: 5 bit two's complement exponent, 11 bit two's complement mantissa
+    let exponent = ((raw as i16) >> 11) as i8;
+    let mantissa = ((raw << 5) as i16) >> 5;
+    mantissa as f32 * pow2(exponent)
+}
+
+pub fn f32_to_linear11(value: f32) -> u16 {
+    //! Picks the smallest exponent that still fits the mantissa for the best resolution
+    for exponent in -16..=15 {
+        let mantissa = round(value / pow2(exponent));
+        if (-1024..=1023).contains(&mantissa) {
+            return ((exponent as u16 & 0x1F) << 11) | (mantissa as u16 & 0x07FF);
+        }
+    }
+    if value < 0.0 {
+        0x7C00
+    } else {
+        0x7BFF
+    }
+}
+
+/// Data format of VOUT related commands as reported by VOUT_MODE
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VoutMode {
+    Linear(i8),
+    Vid(u8),
+    Direct,
+    Unknown(u8),
+}
+
+impl From<u8> for VoutMode {
+    fn from(value: u8) -> Self {
+        let parameter = value & 0x1F;
+        match value >> 5 {
+            0b000 => VoutMode::Linear(((parameter << 3) as i8) >> 3),
+            0b001 => VoutMode::Vid(parameter),
+            0b010 => VoutMode::Direct,
+            _ => VoutMode::Unknown(value),
+        }
+    }
+}
+
+pub fn linear16_to_f32(raw: u16, exponent: i8) -> f32 {
+    //! LINEAR16: unsigned mantissa, exponent taken from VOUT_MODE
+    raw as f32 * pow2(exponent)
+}
+
+pub fn f32_to_linear16(value: f32, exponent: i8) -> u16 {
+    round(value / pow2(exponent)).clamp(0, u16::MAX as i32) as u16
+}
+
+pub fn decode_vout(raw: u16, mode: VoutMode) -> Option<f32> {
+    //! Only linear mode is defined by the PMBus specification, VID codes are vendor specific
+    match mode {
+        VoutMode::Linear(exponent) => Some(linear16_to_f32(raw, exponent)),
+        _ => None,
+    }
+}
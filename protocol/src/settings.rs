@@ -0,0 +1,258 @@
+//! Layout of the settings as the Epower registers 0x40 to 0x7F present them, with the checks of
+//! every value a host writes and the format of an exported configuration.
+
+use core::ops::Range;
+
+pub const DISPLAY_TOP_MODE_OFFSET: usize = 0;
+pub const DISPLAY_BOTTOM_MODE_OFFSET: usize = 1;
+pub const L1_BOOT_VOLTAGE_OFFSET: usize = 2;
+pub const L2_BOOT_VOLTAGE_OFFSET: usize = 3;
+pub const L1_ENABLED_OFFSET: usize = 4;
+pub const L2_ENABLED_OFFSET: usize = 5;
+pub const DISPLAY_ORIENTATION_OFFSET: usize = 6;
+pub const BOOT_PRESET_OFFSET: usize = 7;
+pub const PRESETS_OFFSET: usize = 8;
+
+/// Number of user presets, selectable as preset 1 to `PRESET_COUNT`.
+///
+/// Preset 0 always refers to the boot voltage and enable settings.
+pub const PRESET_COUNT: u8 = 4;
+pub const PRESET_SIZE: usize = 4;
+pub const PRESET_L1_VID: usize = 0;
+pub const PRESET_L2_VID: usize = 1;
+pub const PRESET_L1_ENABLED: usize = 2;
+pub const PRESET_L2_ENABLED: usize = 3;
+const PRESETS_RANGE: Range<usize> =
+    PRESETS_OFFSET..PRESETS_OFFSET + PRESET_COUNT as usize * PRESET_SIZE;
+/// Length of a preset name, shorter names are padded with 0
+pub const PRESET_NAME_SIZE: usize = 8;
+pub const PRESET_NAMES_SIZE: usize = PRESET_COUNT as usize * PRESET_NAME_SIZE;
+/// Makes the Epower address answer PMBus commands instead of its own register map
+pub const PMBUS_PERSONALITY_OFFSET: usize = 24;
+/// Requires SMBus PEC on the Epower address and on proxied IR3595 transactions
+pub const PEC_OFFSET: usize = 25;
+/// Makes the whole IR3595 read-only over the EVBot connector
+pub const PROXY_LOCK_OFFSET: usize = 26;
+/// Pairs of IR3595 command and permission (0 read-write, 1 read-only, 2 hidden)
+pub const PROXY_ACCESS_RULES_OFFSET: usize = 27;
+const PROXY_ACCESS_RULE_COUNT: usize = 12;
+pub const PROXY_ACCESS_RULE_SIZE: usize = 2;
+pub const PROXY_ACCESS_RULES_RANGE: Range<usize> = PROXY_ACCESS_RULES_OFFSET
+    ..PROXY_ACCESS_RULES_OFFSET + PROXY_ACCESS_RULE_COUNT * PROXY_ACCESS_RULE_SIZE;
+/// EVBot address of the IR3595 proxy, see `slave_address`
+pub const PROXY_ADDRESS_OFFSET: usize = 51;
+/// EVBot address of the Epower itself, see `slave_address`
+pub const EPOWER_ADDRESS_OFFSET: usize = 52;
+/// Address setting value that disables the address
+pub const ADDRESS_DISABLED: u8 = 0xFF;
+/// Keeps written settings in RAM until they are committed
+pub const MANUAL_COMMIT_OFFSET: usize = 53;
+
+/// Highest VID the settings and voltage sweeps accept, the EVC2 profile offers the same range
+pub const MAX_VID: u8 = 0xA0;
+/// Highest mode of a display row, see `display_row` in main.rs
+const MAX_DISPLAY_MODE: u8 = 9;
+
+pub const SETTINGS_VERSION_OFFSET: usize = 59;
+/// Layout of the settings, independent of the firmware version. Raising it needs a migration
+/// from the previous layout in `MIGRATIONS`.
+pub const SETTINGS_SCHEMA_VERSION: u8 = 2;
+/// `MIGRATIONS[n]` turns settings of schema version n + 1 into version n + 2
+const MIGRATIONS: [fn(&mut [u8; 60]); SETTINGS_SCHEMA_VERSION as usize - 1] = [migrate_from_v1];
+
+const VALID_SETTING_OFFSETS: [usize; 14] = [
+    DISPLAY_TOP_MODE_OFFSET,
+    DISPLAY_BOTTOM_MODE_OFFSET,
+    L1_BOOT_VOLTAGE_OFFSET,
+    L2_BOOT_VOLTAGE_OFFSET,
+    L1_ENABLED_OFFSET,
+    L2_ENABLED_OFFSET,
+    DISPLAY_ORIENTATION_OFFSET,
+    BOOT_PRESET_OFFSET,
+    PMBUS_PERSONALITY_OFFSET,
+    PEC_OFFSET,
+    PROXY_LOCK_OFFSET,
+    PROXY_ADDRESS_OFFSET,
+    EPOWER_ADDRESS_OFFSET,
+    MANUAL_COMMIT_OFFSET,
+];
+
+/// Checksum of the stored and the exported settings
+pub const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
+
+pub fn slave_address(value: u8, default: u8) -> Option<u8> {
+    //! 0 selects the default address so that settings of older versions keep it, reserved
+    //! addresses also fall back to it
+    match value {
+        ADDRESS_DISABLED => None,
+        0x08..=0x77 => Some(value),
+        _ => Some(default),
+    }
+}
+
+pub fn is_valid_setting(setting_id: usize) -> bool {
+    VALID_SETTING_OFFSETS.contains(&setting_id)
+        || PRESETS_RANGE.contains(&setting_id)
+        || PROXY_ACCESS_RULES_RANGE.contains(&setting_id)
+}
+
+pub fn is_valid_value(setting_id: usize, value: u8) -> bool {
+    //! `setting_id` has to be a valid setting
+    match setting_id {
+        DISPLAY_TOP_MODE_OFFSET | DISPLAY_BOTTOM_MODE_OFFSET => value <= MAX_DISPLAY_MODE,
+        L1_BOOT_VOLTAGE_OFFSET | L2_BOOT_VOLTAGE_OFFSET => value <= MAX_VID,
+        BOOT_PRESET_OFFSET => value <= PRESET_COUNT,
+        PROXY_ADDRESS_OFFSET | EPOWER_ADDRESS_OFFSET => {
+            matches!(value, 0 | 0x08..=0x77 | ADDRESS_DISABLED)
+        }
+        id if PRESETS_RANGE.contains(&id) => match (id - PRESETS_OFFSET) % PRESET_SIZE {
+            PRESET_L1_VID | PRESET_L2_VID => value <= MAX_VID,
+            _ => value <= 1,
+        },
+        // any IR3595 command, followed by its permission
+        id if PROXY_ACCESS_RULES_RANGE.contains(&id) => {
+            (id - PROXY_ACCESS_RULES_OFFSET).is_multiple_of(PROXY_ACCESS_RULE_SIZE) || value <= 2
+        }
+        // everything else is switched on by 1
+        _ => value <= 1,
+    }
+}
+
+pub fn check_values(first_setting_id: u8, values: &[u8]) -> Result<(), SettingError> {
+    //! Checks `values` for consecutive settings, the error names the first rejected setting
+    let error = (first_setting_id as usize..)
+        .zip(values)
+        .find_map(|(setting_id, &value)| {
+            if !is_valid_setting(setting_id) {
+                Some(SettingError::UnknownSetting(setting_id as u8))
+            } else if !is_valid_value(setting_id, value) {
+                Some(SettingError::OutOfRange(setting_id as u8))
+            } else {
+                None
+            }
+        });
+    match error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Why the last write to the settings was rejected, with the first setting that was rejected
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SettingError {
+    UnknownSetting(u8),
+    OutOfRange(u8),
+    /// An imported blob has the wrong size, a bad checksum or is from a newer firmware
+    InvalidExport,
+    /// A preset name is too long or not printable ASCII, with the number of the preset
+    InvalidName(u8),
+}
+
+impl SettingError {
+    pub fn setting_id(self) -> Option<u8> {
+        match self {
+            SettingError::UnknownSetting(setting_id) | SettingError::OutOfRange(setting_id) => {
+                Some(setting_id)
+            }
+            SettingError::InvalidExport | SettingError::InvalidName(_) => None,
+        }
+    }
+}
+
+impl From<SettingError> for u8 {
+    fn from(error: SettingError) -> Self {
+        match error {
+            SettingError::UnknownSetting(_) => 1,
+            SettingError::OutOfRange(_) => 2,
+            SettingError::InvalidExport => 3,
+            SettingError::InvalidName(_) => 4,
+        }
+    }
+}
+
+/// Size of an exported configuration: all settings including the version, the preset names,
+/// followed by the checksum of both (MSB first)
+pub const EXPORT_SIZE: usize = 60 + PRESET_NAMES_SIZE + 4;
+
+fn is_valid_name(name: &[u8]) -> bool {
+    //! Printable ASCII, only followed by the padding
+    name.len() <= PRESET_NAME_SIZE
+        && name
+            .iter()
+            .skip_while(|&&c| (0x20..=0x7E).contains(&c))
+            .all(|&c| c == 0)
+}
+
+pub fn check_name(number: u8, name: &[u8]) -> Result<(), SettingError> {
+    //! Only the presets 1 to `PRESET_COUNT` have a name
+    match number {
+        1..=PRESET_COUNT if is_valid_name(name) => Ok(()),
+        _ => Err(SettingError::InvalidName(number)),
+    }
+}
+
+pub fn invalid_name(names: &[u8; PRESET_NAMES_SIZE]) -> Option<SettingError> {
+    names
+        .chunks_exact(PRESET_NAME_SIZE)
+        .position(|name| !is_valid_name(name))
+        .map(|index| SettingError::InvalidName(index as u8 + 1))
+}
+
+pub fn export(settings: &[u8; 60], names: &[u8; PRESET_NAMES_SIZE]) -> [u8; EXPORT_SIZE] {
+    //! Presets, access rules and addresses, everything in between that is not a setting is 0.
+    //! The version allows a later firmware to import it.
+    let mut export = [0; EXPORT_SIZE];
+    for setting_id in (0..60).filter(|&setting_id| is_valid_setting(setting_id)) {
+        export[setting_id] = settings[setting_id];
+    }
+    export[SETTINGS_VERSION_OFFSET] = SETTINGS_SCHEMA_VERSION;
+    export[60..EXPORT_SIZE - 4].copy_from_slice(names);
+    let checksum = CRC.checksum(&export[..EXPORT_SIZE - 4]);
+    export[EXPORT_SIZE - 4..].copy_from_slice(&checksum.to_be_bytes());
+    export
+}
+
+pub fn parse_export(export: &[u8]) -> Result<([u8; 60], [u8; PRESET_NAMES_SIZE]), SettingError> {
+    //! Settings of an older firmware are migrated
+    if export.len() != EXPORT_SIZE {
+        return Err(SettingError::InvalidExport);
+    }
+    let (data, checksum) = export.split_at(EXPORT_SIZE - 4);
+    let checksum = u32::from_be_bytes(checksum.try_into().expect("checksum is 4 bytes"));
+    if CRC.checksum(data) != checksum {
+        return Err(SettingError::InvalidExport);
+    }
+    let (settings, names) = data.split_at(60);
+    let settings = migrate(settings.try_into().expect("settings are 60 bytes"))
+        .ok_or(SettingError::InvalidExport)?;
+    let names = names.try_into().expect("names are PRESET_NAMES_SIZE bytes");
+    if let Some(setting_id) = (0..settings.len())
+        .filter(|&setting_id| is_valid_setting(setting_id))
+        .find(|&setting_id| !is_valid_value(setting_id, settings[setting_id]))
+    {
+        return Err(SettingError::OutOfRange(setting_id as u8));
+    }
+    match invalid_name(&names) {
+        Some(error) => Err(error),
+        None => Ok((settings, names)),
+    }
+}
+
+fn migrate_from_v1(_settings: &mut [u8; 60]) {
+    //! Version 1 was stored by firmware 1 and only had the settings up to the display
+    //! orientation. Everything added since is off at 0, which is what version 1 left in place.
+}
+
+pub fn migrate(mut settings: [u8; 60]) -> Option<[u8; 60]> {
+    //! Brings stored settings up to the current schema, `None` if they cannot be used.
+    //! Settings of a newer schema, left behind by a downgrade, are not understood.
+    let version = settings[SETTINGS_VERSION_OFFSET];
+    if version == 0 || version > SETTINGS_SCHEMA_VERSION {
+        return None;
+    }
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(&mut settings);
+    }
+    settings[SETTINGS_VERSION_OFFSET] = SETTINGS_SCHEMA_VERSION;
+    Some(settings)
+}
@@ -0,0 +1,850 @@
+//! Replays the transactions the EVC2 sends over the EVBot connector, with the status codes the
+//! LPC11U6x I2C peripheral reports for them, against the register map of the firmware. Only the
+//! board around it is modelled here.
+
+use epower_protocol::evbot::*;
+use epower_protocol::i2c_driver_states::*;
+use epower_protocol::settings::{self, EXPORT_SIZE, PRESET_NAMES_SIZE, PRESET_NAME_SIZE};
+use epower_protocol::{Action, Device, Handler, SlaveMachine, FILLER_BYTE};
+
+const EPOWER_ADDR: u8 = 0x0E;
+const IR3595_ADDR: u8 = 0x08;
+const FIRMWARE_VERSION: u8 = 5;
+const UID: u32 = 0x1234_5678;
+
+/// Settings register of a setting
+const fn register(setting_id: usize) -> u8 {
+    setting_id as u8 + 64
+}
+
+fn smbus_pec(bytes: &[u8]) -> u8 {
+    //! CRC-8 with polynomial x^8 + x^2 + x + 1, computed independently of the firmware
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Everything around the register map: settings, telemetry and the IR3595 behind I2C0
+struct Epower {
+    settings: [u8; 60],
+    names: [u8; PRESET_NAMES_SIZE],
+    uncommitted: bool,
+    commits: usize,
+    resets: usize,
+    installs: usize,
+    active_preset: u8,
+    monitoring: Monitoring,
+    ir3595: [u8; 256],
+    /// Lone command byte held back until it turns out to be followed by a read
+    ir3595_pending_cmd: Option<u8>,
+    /// Command the open proxied read started at
+    ir3595_read_cmd: Option<u8>,
+    ir3595_reading: bool,
+    /// Register the next byte of a proxied read comes from
+    ir3595_cursor: u8,
+    /// Proxied reads wait for I2C0 and stretch the clock until `Bus::deliver`
+    ir3595_busy: bool,
+    ir3595_writes: Vec<(Vec<u8>, WriteResult)>,
+    reads_finished: usize,
+}
+
+impl Epower {
+    fn new() -> Self {
+        let mut settings = [0; 60];
+        settings[settings::SETTINGS_VERSION_OFFSET] = settings::SETTINGS_SCHEMA_VERSION;
+        Epower {
+            settings,
+            names: [0; PRESET_NAMES_SIZE],
+            uncommitted: false,
+            commits: 0,
+            resets: 0,
+            installs: 0,
+            active_preset: 0,
+            monitoring: Monitoring::default(),
+            ir3595: [0; 256],
+            ir3595_pending_cmd: None,
+            ir3595_read_cmd: None,
+            ir3595_reading: false,
+            ir3595_cursor: 0,
+            ir3595_busy: false,
+            ir3595_writes: Vec::new(),
+            reads_finished: 0,
+        }
+    }
+}
+
+impl Board for Epower {
+    fn setting(&self, setting_id: u8) -> u8 {
+        self.settings[setting_id as usize]
+    }
+
+    fn write_settings(&mut self, first_setting_id: u8, values: &[u8]) {
+        let start = first_setting_id as usize;
+        self.settings[start..start + values.len()].copy_from_slice(values);
+        self.uncommitted = self.settings[settings::MANUAL_COMMIT_OFFSET] == 1;
+    }
+
+    fn has_uncommitted_settings(&self) -> bool {
+        self.uncommitted
+    }
+
+    fn commit_settings(&mut self) {
+        self.commits += 1;
+        self.uncommitted = false;
+    }
+
+    fn discard_settings(&mut self) {
+        self.uncommitted = false;
+    }
+
+    fn restore_default_settings(&mut self) {
+        *self = Epower::new();
+    }
+
+    fn export_settings(&self) -> [u8; EXPORT_SIZE] {
+        settings::export(&self.settings, &self.names)
+    }
+
+    fn import_settings(&mut self, settings: &[u8; 60], names: &[u8; PRESET_NAMES_SIZE]) {
+        self.settings = *settings;
+        self.names = *names;
+    }
+
+    fn print_settings_export(&mut self) {}
+
+    fn preset_name(&self, number: u8) -> [u8; PRESET_NAME_SIZE] {
+        let start = (number as usize - 1) * PRESET_NAME_SIZE;
+        self.names[start..start + PRESET_NAME_SIZE]
+            .try_into()
+            .unwrap()
+    }
+
+    fn set_preset_name(&mut self, number: u8, name: &[u8]) {
+        let start = (number as usize - 1) * PRESET_NAME_SIZE;
+        let padded = &mut self.names[start..start + PRESET_NAME_SIZE];
+        padded.fill(0);
+        padded[..name.len()].copy_from_slice(name);
+    }
+
+    fn active_preset(&self) -> u8 {
+        self.active_preset
+    }
+
+    fn select_preset(&mut self, number: u8) {
+        self.active_preset = number;
+    }
+
+    fn boot_count(&self) -> u32 {
+        3
+    }
+
+    fn epower_address(&self) -> Option<u8> {
+        settings::slave_address(self.settings[settings::EPOWER_ADDRESS_OFFSET], EPOWER_ADDR)
+    }
+
+    fn proxy_address(&self) -> Option<u8> {
+        settings::slave_address(self.settings[settings::PROXY_ADDRESS_OFFSET], IR3595_ADDR)
+            .filter(|&addr| Some(addr) != self.epower_address())
+    }
+
+    fn monitoring(&self) -> Monitoring {
+        self.monitoring
+    }
+
+    fn is_loop_enabled(&self, index: usize) -> bool {
+        self.monitoring.loops[index].enabled
+    }
+
+    fn reset_energy(&mut self) {}
+
+    fn configure_sweep(&mut self, _config: &[u8]) {}
+
+    fn start_sweep(&mut self) {}
+
+    fn abort_sweep(&mut self) {}
+
+    fn sweep_state(&self) -> u8 {
+        0
+    }
+
+    fn sweep_record_count(&self) -> u8 {
+        0
+    }
+
+    fn history_len(&self) -> u16 {
+        0
+    }
+
+    fn is_history_paused(&self) -> bool {
+        false
+    }
+
+    fn set_history_paused(&mut self, _paused: bool) {}
+
+    fn clear_history(&mut self) {}
+
+    fn journal_len(&self) -> u16 {
+        self.ir3595_writes.len() as u16
+    }
+
+    fn is_journal_paused(&self) -> bool {
+        false
+    }
+
+    fn set_journal_paused(&mut self, _paused: bool) {}
+
+    fn clear_journal(&mut self) {}
+
+    fn request_journal_dump(&mut self) {}
+
+    fn stream_byte(&self, _stream: Stream, _start: usize, _index: usize) -> Option<u8> {
+        None
+    }
+
+    fn update_state(&self) -> u8 {
+        0
+    }
+
+    fn next_update_block(&self) -> u16 {
+        0
+    }
+
+    fn start_update(&mut self, _length: u32, _crc: u32) {}
+
+    fn write_update_block(&mut self, _block: u16, _data: &[u8]) {}
+
+    fn install_update(&mut self) {
+        self.installs += 1;
+    }
+
+    fn reset(&mut self) {
+        self.resets += 1;
+    }
+
+    fn enter_isp(&mut self) {}
+
+    fn settle_proxy(&mut self) {}
+
+    fn set_pending_ir3595_cmd(&mut self, cmd: u8, _writable: bool) {
+        self.ir3595_pending_cmd = Some(cmd);
+    }
+
+    fn pending_ir3595_cmd(&self) -> Option<u8> {
+        self.ir3595_pending_cmd
+    }
+
+    fn discard_pending_ir3595_cmd(&mut self) {
+        self.ir3595_pending_cmd = None;
+    }
+
+    fn write_ir3595(&mut self, data: &[u8]) {
+        self.ir3595_pending_cmd = None;
+        if let [cmd, ref values @ ..] = *data {
+            for (offset, &value) in values.iter().enumerate() {
+                self.ir3595[cmd.wrapping_add(offset as u8) as usize] = value;
+            }
+        }
+        self.ir3595_writes
+            .push((data.to_vec(), WriteResult::Forwarded));
+    }
+
+    fn start_ir3595_read(&mut self, _single: bool) {
+        self.ir3595_read_cmd = self.ir3595_pending_cmd.take();
+        // a read without command byte continues wherever the IR3595 left off
+        if let Some(cmd) = self.ir3595_read_cmd {
+            self.ir3595_cursor = cmd;
+        }
+        self.ir3595_reading = true;
+    }
+
+    fn next_ir3595_read(&mut self) -> bool {
+        self.ir3595_reading
+    }
+
+    fn finish_ir3595_read(&mut self) {
+        self.ir3595_reading = false;
+        self.reads_finished += 1;
+    }
+
+    fn record_ir3595_write(&mut self, write: &[u8], result: WriteResult) {
+        self.ir3595_writes.push((write.to_vec(), result));
+    }
+}
+
+/// The EVBot connector as seen by the peripheral, only the addresses in ADR0/ADR1 raise states
+struct Bus {
+    machine: SlaveMachine<RECIEVE_BUFFER_SIZE>,
+    state: EvbotState,
+    epower: Epower,
+}
+
+impl Bus {
+    fn new() -> Self {
+        Bus {
+            machine: SlaveMachine::new(),
+            state: EvbotState::new(FIRMWARE_VERSION, UID),
+            epower: Epower::new(),
+        }
+    }
+
+    fn with_setting(setting_id: usize, value: u8) -> Self {
+        let mut bus = Bus::new();
+        assert!(bus.write(EPOWER_ADDR, &[register(setting_id), value]));
+        bus
+    }
+
+    fn step_stretched(&mut self, status: u32, data: u8) -> Action {
+        //! The action as it is, proxied reads may leave SI set
+        let mut evbot = Evbot::new(&mut self.state, &mut self.epower);
+        self.machine.step(status, data, &mut evbot)
+    }
+
+    fn step(&mut self, status: u32, data: u8) -> Action {
+        let mut action = self.step_stretched(status, data);
+        if action.stretch && !self.epower.ir3595_busy {
+            action = self.deliver();
+        }
+        assert!(action.clear_interrupt, "state {status:#04X} left SI set");
+        action
+    }
+
+    fn deliver(&mut self) -> Action {
+        //! I2C0 got the byte of a proxied read, like `I2C1State::send_proxied_byte`
+        let cursor = &mut self.epower.ir3595_cursor;
+        let value = self.epower.ir3595[*cursor as usize];
+        *cursor = cursor.wrapping_add(1);
+        let value = self
+            .state
+            .ir3595_read_done(self.epower.ir3595_read_cmd, ReadEvent::Byte(value));
+        Action::send(value)
+    }
+
+    fn is_own_address(&self, addr: u8) -> bool {
+        let addr = Some(addr);
+        addr == self.epower.epower_address() || addr == self.epower.proxy_address()
+    }
+
+    fn start(&mut self, addr: u8, read: bool) -> Option<Action> {
+        //! SLA+R/W, `None` when the address is not acknowledged
+        if !self.is_own_address(addr) {
+            return None;
+        }
+        let action = if read {
+            self.step(SLAVE_READ_RECIEVED_AND_ACKED, (addr << 1) | 1)
+        } else {
+            self.step(SLAVE_WRITE_RECIEVED_AND_ACKED, addr << 1)
+        };
+        assert!(action.ack);
+        Some(action)
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            let action = self.step(RECIEVED_DATA_AND_ACKED, byte);
+            assert!(action.ack);
+        }
+    }
+
+    fn stop(&mut self) {
+        let action = self.step(RECIEVED_STOP_OR_REPEAT, 0);
+        assert!(action.ack);
+    }
+
+    fn receive(&mut self, first: Action, len: usize) -> Vec<u8> {
+        //! The master acknowledges every byte but the last one
+        let mut bytes = vec![first.data.expect("no data for the first byte")];
+        while bytes.len() < len {
+            let action = self.step(SEND_DATA_AND_RECIEVED_ACK, 0);
+            bytes.push(action.data.expect("no data loaded"));
+        }
+        let action = self.step(SEND_DATA_AND_RECIEVED_NOT_ACK, 0);
+        assert!(action.ack, "own address no longer recognized");
+        bytes
+    }
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> bool {
+        if self.start(addr, false).is_none() {
+            return false;
+        }
+        self.send(bytes);
+        self.stop();
+        true
+    }
+
+    fn write_with_pec(&mut self, addr: u8, bytes: &[u8]) -> bool {
+        let mut write = bytes.to_vec();
+        write.push(smbus_pec(&[&[addr << 1], bytes].concat()));
+        self.write(addr, &write)
+    }
+
+    fn read(&mut self, addr: u8, cmd: u8, len: usize) -> Option<Vec<u8>> {
+        //! Command byte, repeated START, then `len` bytes
+        self.start(addr, false)?;
+        self.send(&[cmd]);
+        self.stop();
+        let first = self.start(addr, true)?;
+        Some(self.receive(first, len))
+    }
+
+    fn settings_error(&mut self) -> [u8; 2] {
+        let error = self.read(EPOWER_ADDR, SETTINGS_ERROR_CMD, 2).unwrap();
+        error.try_into().unwrap()
+    }
+}
+
+#[test]
+fn scan_finds_both_devices() {
+    let mut bus = Bus::new();
+    let found: Vec<u8> = (0x08..=0x77).filter(|&addr| bus.write(addr, &[])).collect();
+    assert_eq!(found, [IR3595_ADDR, EPOWER_ADDR]);
+    // a quick write leaves nothing behind
+    assert!(bus.epower.ir3595_writes.is_empty());
+    assert_eq!(bus.epower.ir3595_pending_cmd, None);
+    let first = bus.start(EPOWER_ADDR, true).unwrap();
+    assert_eq!(bus.receive(first, 1), [FILLER_BYTE]);
+}
+
+#[test]
+fn version_read() {
+    let mut bus = Bus::new();
+    assert_eq!(bus.read(EPOWER_ADDR, 1, 1), Some(vec![FIRMWARE_VERSION]));
+}
+
+#[test]
+fn uid_read_is_lsb_first() {
+    let mut bus = Bus::new();
+    let uid = bus.read(EPOWER_ADDR, 2, 4).unwrap();
+    assert_eq!(uid, [0x78, 0x56, 0x34, 0x12]);
+}
+
+#[test]
+fn settings_write_and_read_back() {
+    let mut bus = Bus::new();
+    assert!(bus.write(EPOWER_ADDR, &[0x40, 1]));
+    // consecutive bytes go to consecutive settings: both boot VIDs and L1 enabled
+    assert!(bus.write(EPOWER_ADDR, &[0x42, 0x50, 0x60, 1]));
+    assert_eq!(bus.epower.settings[..5], [1, 0, 0x50, 0x60, 1]);
+    assert_eq!(bus.read(EPOWER_ADDR, 0x40, 1), Some(vec![1]));
+    assert_eq!(bus.read(EPOWER_ADDR, 0x42, 3), Some(vec![0x50, 0x60, 1]));
+    assert_eq!(bus.settings_error(), [0, 0]);
+}
+
+#[test]
+fn reads_auto_increment_into_the_next_register() {
+    let mut bus = Bus::new();
+    bus.epower.monitoring.adc_mv = [1250, 1100, 12050, 5010, 3300];
+    assert_eq!(bus.read(EPOWER_ADDR, 0x80, 2), Some(vec![0x04, 0xE2]));
+    assert_eq!(bus.read(EPOWER_ADDR, 0x82, 2), Some(vec![0x2F, 0x12]));
+    // all rails in one go
+    let rails = bus.read(EPOWER_ADDR, 0x80, 10).unwrap();
+    let rails: Vec<u16> = rails
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]))
+        .collect();
+    assert_eq!(rails, bus.epower.monitoring.adc_mv);
+    // registers of different sizes: the version, then the UID
+    let constants = bus.read(EPOWER_ADDR, 1, 5).unwrap();
+    assert_eq!(constants, [FIRMWARE_VERSION, 0x78, 0x56, 0x34, 0x12]);
+    // registers without a setting read as 0
+    bus.epower.settings[settings::MANUAL_COMMIT_OFFSET] = 1;
+    bus.epower.settings[settings::MANUAL_COMMIT_OFFSET + 1] = 7;
+    let settings = bus.read(EPOWER_ADDR, 0x75, 2).unwrap();
+    assert_eq!(settings, [1, 0]);
+}
+
+#[test]
+fn out_of_range_setting_is_rejected() {
+    let mut bus = Bus::new();
+    // L2 boot VID above the EVC2 range, the valid L1 boot VID before it is not written either
+    assert!(bus.write(EPOWER_ADDR, &[0x42, 0x50, 0xA1]));
+    assert_eq!(bus.epower.settings[..4], [0; 4]);
+    assert_eq!(bus.settings_error(), [2, 0x43]);
+    assert!(bus.write(EPOWER_ADDR, &[register(settings::PROXY_LOCK_OFFSET), 2]));
+    assert_eq!(bus.settings_error(), [2, 0x5A]);
+    assert!(bus.write(
+        EPOWER_ADDR,
+        &[register(settings::EPOWER_ADDRESS_OFFSET), 0x78]
+    ));
+    assert_eq!(bus.settings_error(), [2, 0x74]);
+    assert_eq!(bus.read(EPOWER_ADDR, 1, 1), Some(vec![FIRMWARE_VERSION]));
+    // the next accepted write clears the error
+    assert!(bus.write(EPOWER_ADDR, &[0x42, 0xA0]));
+    assert_eq!(bus.settings_error(), [0, 0]);
+}
+
+#[test]
+fn unknown_setting_is_rejected() {
+    let mut bus = Bus::new();
+    assert!(bus.write(EPOWER_ADDR, &[0x76, 0]));
+    assert_eq!(bus.settings_error(), [1, 0x76]);
+    // a write running past the manual commit setting into the unused registers
+    assert!(bus.write(EPOWER_ADDR, &[0x75, 1, 0]));
+    assert_eq!(bus.epower.settings[settings::MANUAL_COMMIT_OFFSET], 0);
+    assert_eq!(bus.settings_error(), [1, 0x76]);
+    // the settings version is not writable
+    assert!(bus.write(
+        EPOWER_ADDR,
+        &[register(settings::SETTINGS_VERSION_OFFSET), 1]
+    ));
+    assert_eq!(bus.settings_error(), [1, 0x7B]);
+}
+
+#[test]
+fn access_rules_take_any_command() {
+    let mut bus = Bus::new();
+    let rule = register(settings::PROXY_ACCESS_RULES_RANGE.start);
+    assert!(bus.write(EPOWER_ADDR, &[rule, 0xFF, 2]));
+    assert_eq!(bus.settings_error(), [0, 0]);
+    assert!(bus.write(EPOWER_ADDR, &[rule + 2, 0x21, 3]));
+    assert_eq!(bus.settings_error(), [2, rule + 3]);
+}
+
+#[test]
+fn preset_names() {
+    let mut bus = Bus::new();
+    assert!(bus.write(
+        EPOWER_ADDR,
+        &[PRESET_NAMES_CMD + 1, b'B', b'e', b'n', b'c', b'h']
+    ));
+    assert_eq!(
+        bus.read(EPOWER_ADDR, PRESET_NAMES_CMD + 1, PRESET_NAME_SIZE),
+        Some(b"Bench\0\0\0".to_vec())
+    );
+    assert_eq!(bus.settings_error(), [0, 0]);
+    // control characters and names longer than 8 characters are rejected
+    assert!(bus.write(EPOWER_ADDR, &[PRESET_NAMES_CMD + 1, b'\n']));
+    assert_eq!(bus.settings_error(), [4, PRESET_NAMES_CMD + 1]);
+    assert!(bus.write(
+        EPOWER_ADDR,
+        &[LAST_PRESET_NAME_CMD, b'x', 9, 9, 9, 9, 9, 9, 9, 9]
+    ));
+    assert_eq!(bus.settings_error(), [4, LAST_PRESET_NAME_CMD]);
+    assert_eq!(&bus.epower.names[8..13], b"Bench");
+    assert_eq!(bus.epower.names[24..], [0; 8]);
+}
+
+#[test]
+fn settings_export_imports_again() {
+    let mut bus = Bus::new();
+    assert!(bus.write(EPOWER_ADDR, &[0x42, 0x50, 0x60]));
+    assert!(bus.write(EPOWER_ADDR, &[PRESET_NAMES_CMD, b'O', b'C']));
+    let export = bus
+        .read(EPOWER_ADDR, SETTINGS_EXPORT_CMD, EXPORT_SIZE)
+        .unwrap();
+    assert_eq!(export[2..4], [0x50, 0x60]);
+    // the whole export is streamed and followed by filler
+    let longer = bus
+        .read(EPOWER_ADDR, SETTINGS_EXPORT_CMD, EXPORT_SIZE + 1)
+        .unwrap();
+    assert_eq!(longer[..EXPORT_SIZE], export);
+    assert_eq!(longer[EXPORT_SIZE], FILLER_BYTE);
+
+    let mut bus = Bus::new();
+    assert!(bus.write(
+        EPOWER_ADDR,
+        &[[SETTINGS_IMPORT_CMD].as_slice(), &export].concat()
+    ));
+    assert_eq!(bus.settings_error(), [0, 0]);
+    assert_eq!(bus.epower.settings[2..4], [0x50, 0x60]);
+    assert_eq!(&bus.epower.names[..2], b"OC");
+}
+
+#[test]
+fn damaged_settings_export_is_rejected() {
+    let mut bus = Bus::new();
+    let mut export = bus
+        .read(EPOWER_ADDR, SETTINGS_EXPORT_CMD, EXPORT_SIZE)
+        .unwrap();
+    export[2] = 0x50;
+    assert!(bus.write(
+        EPOWER_ADDR,
+        &[[SETTINGS_IMPORT_CMD].as_slice(), &export].concat()
+    ));
+    assert_eq!(bus.settings_error(), [3, 0]);
+    assert!(bus.write(EPOWER_ADDR, &[SETTINGS_IMPORT_CMD, 0]));
+    assert_eq!(bus.settings_error(), [3, 0]);
+    assert_eq!(bus.epower.settings[2], 0);
+}
+
+#[test]
+fn pec_write_is_accepted() {
+    let mut bus = Bus::with_setting(settings::PEC_OFFSET, 1);
+    assert!(bus.write_with_pec(EPOWER_ADDR, &[0x42, 0x50]));
+    assert_eq!(bus.epower.settings[2], 0x50);
+    // a lone command byte carries no PEC
+    assert!(bus.write(EPOWER_ADDR, &[0x42]));
+    let first = bus.start(EPOWER_ADDR, true).unwrap();
+    assert_eq!(bus.receive(first, 1), [0x50]);
+}
+
+#[test]
+fn bad_pec_drops_the_write_and_is_counted() {
+    let mut bus = Bus::with_setting(settings::PEC_OFFSET, 1);
+    let pec = smbus_pec(&[EPOWER_ADDR << 1, 0x42, 0x50]);
+    assert!(bus.write(EPOWER_ADDR, &[0x42, 0x50, pec ^ 1]));
+    assert_eq!(bus.epower.settings[2], 0);
+    // without PEC the value is taken for the PEC of the command byte
+    assert!(bus.write(EPOWER_ADDR, &[0x42, 0x50]));
+    assert_eq!(bus.epower.settings[2], 0);
+    let errors = bus.read(EPOWER_ADDR, EPOWER_PEC_ERRORS_CMD, 2).unwrap();
+    assert_eq!(errors, [0, 2]);
+}
+
+#[test]
+fn reads_append_the_pec_instead_of_the_next_register() {
+    let mut bus = Bus::with_setting(settings::PEC_OFFSET, 1);
+    let addr = EPOWER_ADDR << 1;
+    let version = bus.read(EPOWER_ADDR, 1, 3).unwrap();
+    let pec = smbus_pec(&[addr, 1, addr | 1, FIRMWARE_VERSION]);
+    assert_eq!(version, [FIRMWARE_VERSION, pec, 0xFF]);
+    bus.epower.monitoring.adc_mv[0] = 1250;
+    let rail = bus.read(EPOWER_ADDR, 0x80, 3).unwrap();
+    assert_eq!(
+        rail,
+        [0x04, 0xE2, smbus_pec(&[addr, 0x80, addr | 1, 0x04, 0xE2])]
+    );
+}
+
+#[test]
+fn proxied_read_with_pec() {
+    let mut bus = Bus::with_setting(settings::PEC_OFFSET, 1);
+    bus.epower.ir3595[0x8D] = 0x42;
+    assert!(bus.write_with_pec(IR3595_ADDR, &[0x8D]));
+    let first = bus.start(IR3595_ADDR, true).unwrap();
+    let addr = IR3595_ADDR << 1;
+    let pec = smbus_pec(&[addr, 0x8D, addr | 1, 0x42]);
+    assert_eq!(bus.receive(first, 3), [0x42, pec, 0xFF]);
+    // the PEC covers the command, so a read without one fails
+    let first = bus.start(IR3595_ADDR, true).unwrap();
+    let read = bus.receive(first, 2);
+    assert_eq!(read[0], 0xFF);
+    assert_ne!(read[1], smbus_pec(&[addr, 0, addr | 1, 0xFF]));
+    let failed = bus.read(EPOWER_ADDR, FAILED_IR3595_READS_CMD, 2).unwrap();
+    assert_eq!(failed[..2], [0, 1]);
+}
+
+#[test]
+fn proxied_write_with_bad_pec_is_not_forwarded() {
+    let mut bus = Bus::with_setting(settings::PEC_OFFSET, 1);
+    assert!(bus.write(IR3595_ADDR, &[0x21, 0x5A, 0]));
+    assert_eq!(bus.epower.ir3595[0x21], 0);
+    assert_eq!(
+        bus.epower.ir3595_writes,
+        [(vec![0x21, 0x5A, 0], WriteResult::PecError)]
+    );
+    assert!(bus.write_with_pec(IR3595_ADDR, &[0x21, 0x5A]));
+    assert_eq!(bus.epower.ir3595[0x21], 0x5A);
+    let errors = bus.read(EPOWER_ADDR, IR3595_PEC_ERRORS_CMD, 2).unwrap();
+    assert_eq!(errors[..2], [0, 1]);
+}
+
+#[test]
+fn confirmation_key_is_the_complement_of_the_command() {
+    let mut bus = Bus::new();
+    for key in [0, 0xFF, SOFT_RESET_CMD] {
+        assert!(bus.write(EPOWER_ADDR, &[SOFT_RESET_CMD, key]));
+    }
+    assert_eq!(bus.epower.resets, 0);
+    assert!(bus.write(EPOWER_ADDR, &[SOFT_RESET_CMD, !SOFT_RESET_CMD]));
+    assert_eq!(bus.epower.resets, 1);
+
+    assert!(bus.write(EPOWER_ADDR, &[UPDATE_INSTALL_CMD, 1]));
+    assert_eq!(bus.epower.installs, 0);
+    assert!(bus.write(EPOWER_ADDR, &[UPDATE_INSTALL_CMD, !UPDATE_INSTALL_CMD]));
+    assert_eq!(bus.epower.installs, 1);
+}
+
+#[test]
+fn manual_commit() {
+    let mut bus = Bus::with_setting(settings::MANUAL_COMMIT_OFFSET, 1);
+    assert!(bus.write(EPOWER_ADDR, &[0x42, 0x50]));
+    assert_eq!(bus.read(EPOWER_ADDR, COMMIT_SETTINGS_CMD, 1), Some(vec![1]));
+    assert!(bus.write(EPOWER_ADDR, &[COMMIT_SETTINGS_CMD, 1]));
+    assert_eq!(bus.epower.commits, 0);
+    assert!(bus.write(EPOWER_ADDR, &[COMMIT_SETTINGS_CMD, !COMMIT_SETTINGS_CMD]));
+    assert_eq!(bus.epower.commits, 1);
+    assert_eq!(bus.read(EPOWER_ADDR, COMMIT_SETTINGS_CMD, 1), Some(vec![0]));
+}
+
+#[test]
+fn preset_select_is_limited_to_the_presets() {
+    let mut bus = Bus::new();
+    assert!(bus.write(EPOWER_ADDR, &[PRESET_SELECT_CMD, 5]));
+    assert_eq!(bus.read(EPOWER_ADDR, PRESET_SELECT_CMD, 1), Some(vec![0]));
+    assert!(bus.write(EPOWER_ADDR, &[PRESET_SELECT_CMD, 4]));
+    assert_eq!(bus.read(EPOWER_ADDR, PRESET_SELECT_CMD, 1), Some(vec![4]));
+}
+
+#[test]
+fn moved_epower_address() {
+    let mut bus = Bus::with_setting(settings::EPOWER_ADDRESS_OFFSET, 0x20);
+    assert!(!bus.write(EPOWER_ADDR, &[]));
+    assert_eq!(bus.read(0x20, 1, 1), Some(vec![FIRMWARE_VERSION]));
+    // the Epower wins over the proxy
+    assert!(bus.write(
+        0x20,
+        &[register(settings::EPOWER_ADDRESS_OFFSET), IR3595_ADDR]
+    ));
+    assert_eq!(bus.read(IR3595_ADDR, 1, 1), Some(vec![FIRMWARE_VERSION]));
+}
+
+#[test]
+fn pmbus_personality() {
+    let mut bus = Bus::with_setting(settings::PMBUS_PERSONALITY_OFFSET, 1);
+    assert_eq!(bus.read(EPOWER_ADDR, 0x98, 1), Some(vec![0x22]));
+    // MFR_ID is a block read
+    assert_eq!(bus.read(EPOWER_ADDR, 0x99, 5), Some(b"\x04EVGA".to_vec()));
+    // registers do not auto increment
+    assert_eq!(bus.read(EPOWER_ADDR, 0x98, 2), Some(vec![0x22, 0xFF]));
+    // an unknown command sets CML in STATUS_WORD, sent LSB first
+    assert_eq!(bus.read(EPOWER_ADDR, 0x01, 1), Some(vec![0xFF]));
+    assert_eq!(bus.read(EPOWER_ADDR, 0x79, 2), Some(vec![0x43, 0x08]));
+    assert!(bus.write(EPOWER_ADDR, &[PMBUS_PERSONALITY_CMD, 0]));
+    assert_eq!(bus.read(EPOWER_ADDR, 1, 1), Some(vec![FIRMWARE_VERSION]));
+}
+
+#[test]
+fn command_survives_a_stop_before_the_read() {
+    //! The EVC2 sends the command in its own transaction instead of using a repeated START
+    let mut bus = Bus::new();
+    assert!(bus.write(EPOWER_ADDR, &[1]));
+    let first = bus.start(EPOWER_ADDR, true).unwrap();
+    assert_eq!(bus.receive(first, 1), [FIRMWARE_VERSION]);
+}
+
+#[test]
+fn read_without_command_sends_filler() {
+    let mut bus = Bus::new();
+    let first = bus.start(EPOWER_ADDR, true).unwrap();
+    assert_eq!(bus.receive(first, 2), [FILLER_BYTE, FILLER_BYTE]);
+}
+
+#[test]
+fn ir3595_write_and_read() {
+    let mut bus = Bus::new();
+    assert!(bus.write(IR3595_ADDR, &[0x21, 0x5A]));
+    assert_eq!(
+        bus.epower.ir3595_writes,
+        [(vec![0x21, 0x5A], WriteResult::Forwarded)]
+    );
+    assert_eq!(bus.read(IR3595_ADDR, 0x21, 1), Some(vec![0x5A]));
+    // a read without command byte goes on after the last one
+    bus.epower.ir3595[0x22] = 0xA5;
+    let first = bus.start(IR3595_ADDR, true).unwrap();
+    assert_eq!(bus.receive(first, 1), [0xA5]);
+}
+
+#[test]
+fn proxy_lock_denies_writes() {
+    let mut bus = Bus::with_setting(settings::PROXY_LOCK_OFFSET, 1);
+    assert!(bus.write(IR3595_ADDR, &[0x21, 0x5A]));
+    assert_eq!(bus.epower.ir3595[0x21], 0);
+    assert_eq!(
+        bus.epower.ir3595_writes,
+        [(vec![0x21, 0x5A], WriteResult::Denied)]
+    );
+    // reads still work
+    bus.epower.ir3595[0x21] = 0x33;
+    assert_eq!(bus.read(IR3595_ADDR, 0x21, 1), Some(vec![0x33]));
+    let denied = bus
+        .read(EPOWER_ADDR, DENIED_IR3595_ACCESSES_CMD, 2)
+        .unwrap();
+    assert_eq!(denied, [0, 1]);
+}
+
+#[test]
+fn hidden_ir3595_command_is_not_read() {
+    let mut bus = Bus::new();
+    let rule = register(settings::PROXY_ACCESS_RULES_RANGE.start);
+    assert!(bus.write(EPOWER_ADDR, &[rule, 0x21, 2]));
+    bus.epower.ir3595[0x21] = 0x33;
+    assert_eq!(bus.read(IR3595_ADDR, 0x21, 1), Some(vec![0xFF]));
+    assert_eq!(bus.read(IR3595_ADDR, 0x20, 1), Some(vec![0]));
+}
+
+#[test]
+fn every_read_ends() {
+    let mut bus = Bus::new();
+    bus.read(EPOWER_ADDR, 0x80, 2);
+    bus.read(IR3595_ADDR, 0x21, 1);
+    assert_eq!(bus.epower.reads_finished, 2);
+    assert!(!bus.epower.ir3595_reading);
+}
+
+#[test]
+fn long_writes_are_truncated() {
+    let mut bus = Bus::new();
+    let write: Vec<u8> = (0..120).collect();
+    assert!(bus.write(IR3595_ADDR, &write));
+    assert_eq!(
+        bus.epower.ir3595_writes,
+        [(
+            write[..RECIEVE_BUFFER_SIZE].to_vec(),
+            WriteResult::Forwarded
+        )]
+    );
+}
+
+#[test]
+fn bus_error_recovers() {
+    let mut bus = Bus::new();
+    bus.start(EPOWER_ADDR, false);
+    bus.send(&[0x40, 1]);
+    let action = bus.step(BUS_ERROR, 0);
+    assert!(action.stop && action.ack);
+    // the interrupted write is dropped and the next transaction works as usual
+    assert_eq!(bus.epower.settings[0], 0);
+    assert_eq!(bus.read(EPOWER_ADDR, BUS_ERRORS_CMD, 2), Some(vec![0, 1]));
+}
+
+#[test]
+fn general_call_is_ignored() {
+    let mut bus = Bus::new();
+    bus.step(GENERAL_CALL_RECIEVED, 0);
+    let action = bus.step(GENERAL_CALL_DATA_AND_ACKED, 0x06);
+    assert!(action.ack && action.data.is_none());
+    assert_eq!(bus.epower.resets, 0);
+    assert_eq!(bus.epower.settings, Epower::new().settings);
+}
+
+#[test]
+fn no_information_does_nothing() {
+    let mut bus = Bus::new();
+    let action = bus.step_stretched(NO_INFORMATION, 0);
+    assert_eq!(action, Action::default());
+}
+
+#[test]
+fn proxied_read_stretches_until_i2c0_is_done() {
+    let mut bus = Bus::new();
+    bus.epower.ir3595[0x8D] = 0x42;
+    bus.epower.ir3595[0x8E] = 0x43;
+    assert!(bus.write(IR3595_ADDR, &[0x8D]));
+    bus.epower.ir3595_busy = true;
+    let action = bus.step_stretched(SLAVE_READ_RECIEVED_AND_ACKED, (IR3595_ADDR << 1) | 1);
+    assert!(action.stretch && !action.clear_interrupt && action.data.is_none());
+    // the first byte is sent with `Action::send` once I2C0 delivered it, the read goes on
+    assert_eq!(bus.deliver(), Action::send(0x42));
+    let action = bus.step_stretched(SEND_DATA_AND_RECIEVED_ACK, 0);
+    assert!(action.stretch);
+    assert_eq!(bus.deliver(), Action::send(0x43));
+    bus.epower.ir3595_busy = false;
+    assert!(bus.step(SEND_DATA_AND_RECIEVED_NOT_ACK, 0).ack);
+    assert_eq!(bus.epower.reads_finished, 1);
+}
+
+#[test]
+fn handler_selects_by_the_address_settings() {
+    let mut state = EvbotState::new(FIRMWARE_VERSION, UID);
+    let mut epower = Epower::new();
+    epower.settings[settings::PROXY_ADDRESS_OFFSET] = settings::ADDRESS_DISABLED;
+    let mut evbot = Evbot::new(&mut state, &mut epower);
+    assert_eq!(evbot.select(EPOWER_ADDR), Some(Device::Epower));
+    assert_eq!(evbot.select(IR3595_ADDR), None);
+}
@@ -0,0 +1,217 @@
+//! Resident bootloader in flash sector 0, it starts the firmware and installs updates.
+//!
+//! Every firmware image carries a copy, but an update never overwrites sector 0, so the copy
+//! flashed over USB stays. At every reset it looks for an install request left in EEPROM by
+//! `Updater::install`. If the staged image still matches its CRC and its vector table, it
+//! replaces the firmware and the request is cleared. An install that was cut short by a power
+//! loss simply runs again at the next power up.
+//!
+//! The firmware sectors are erased while this runs, so nothing in here may call into them: no
+//! panics, no memcpy or memset inserted by the compiler, no division and no bounds checks. Memory
+//! is accessed in assembly, arrays are never copied or initialised as a whole but filled word by
+//! word, all arithmetic is wrapping and only the IAP routines in ROM are called. Unoptimised code
+//! may still call into compiler helpers, so debug builds are optimised as well, see Cargo.toml.
+
+// `contains` is not inlined in debug builds
+#![allow(clippy::manual_range_contains)]
+
+use crate::rom::IAP_ENTRY;
+use crate::update::{
+    APPLICATION_ADDR, APPLICATION_SIZE, FIRST_APPLICATION_SECTOR, LAST_APPLICATION_SECTOR,
+    PAGE_SIZE, RAM_END, RAM_START, STAGING_ADDR,
+};
+
+/// EEPROM address of the install request: marker, image length and CRC32, each 4 bytes LSB first.
+/// It follows the preset names, see settings.rs.
+pub const INSTALL_REQUEST_ADDR: u32 = 0x580;
+/// Marker of a pending request, neither erased EEPROM nor a cleared request match it
+pub const INSTALL_REQUESTED: u32 = 0x494E_5354;
+/// The IAP routines use the top 32 bytes of RAM
+const STACK_START: u32 = RAM_END - 32;
+/// The bootloader runs on the 12 MHz IRC, the firmware clocks up on its own
+const CCLK_KHZ: u32 = 12_000;
+const VTOR: u32 = 0xE000_ED08;
+/// The reset vector has to point behind the vector table of the firmware
+const FIRST_CODE_ADDR: u32 = APPLICATION_ADDR + 8;
+const PAGE_LEN: u32 = PAGE_SIZE as u32;
+/// Status of a successful IAP command
+const CMD_SUCCESS: u32 = 0;
+
+#[repr(C)]
+struct VectorTable {
+    initial_stack_pointer: u32,
+    reset: unsafe extern "C" fn() -> !,
+    nmi: unsafe extern "C" fn() -> !,
+    hard_fault: unsafe extern "C" fn() -> !,
+    /// Left empty like in the vector table of the firmware
+    reserved: [u32; 4],
+}
+
+#[used]
+#[link_section = ".boot.vector_table"]
+static BOOT_VECTOR_TABLE: VectorTable = VectorTable {
+    initial_stack_pointer: STACK_START,
+    reset,
+    nmi: halt,
+    hard_fault: halt,
+    reserved: [0; 4],
+};
+
+// The statics below are not initialised before the firmware starts, each word is written before
+// it is read.
+
+/// IAP sources have to be in RAM and word aligned
+static mut PAGE_BUFFER: [u32; PAGE_SIZE / 4] = [0; PAGE_SIZE / 4];
+/// Command code and parameters of an IAP call
+static mut IAP_COMMAND: [u32; 5] = [0; 5];
+/// Status and results of an IAP call
+static mut IAP_RESULT: [u32; 4] = [0; 4];
+/// The install request as read from EEPROM
+static mut REQUEST: [u32; 3] = [0; 3];
+
+#[link_section = ".boot.text"]
+unsafe extern "C" fn reset() -> ! {
+    let request = core::ptr::addr_of_mut!(REQUEST) as u32;
+    iap(62, INSTALL_REQUEST_ADDR, request, 12, CCLK_KHZ);
+    let marker = read_word(request);
+    let length = read_word(request.wrapping_add(4));
+    let crc = read_word(request.wrapping_add(8));
+    if marker == INSTALL_REQUESTED {
+        // a damaged image does not get any better, a failed install is tried again
+        if !is_valid_image(length, crc) || install(length) {
+            // overwrites the marker
+            write_word(request, 0);
+            iap(61, INSTALL_REQUEST_ADDR, request, 4, CCLK_KHZ);
+        }
+    }
+    start_firmware()
+}
+
+#[link_section = ".boot.text"]
+unsafe extern "C" fn halt() -> ! {
+    loop {
+        core::arch::asm!("nop");
+    }
+}
+
+#[link_section = ".boot.text"]
+unsafe fn iap(code: u32, param0: u32, param1: u32, param2: u32, param3: u32) -> u32 {
+    //! Calls the IAP routines in ROM with the command and its parameters, returns the status
+    let iap_entry =
+        core::mem::transmute::<usize, unsafe extern "C" fn(*mut u32, *mut u32)>(IAP_ENTRY);
+    let command = core::ptr::addr_of_mut!(IAP_COMMAND) as u32;
+    write_word(command, code);
+    write_word(command.wrapping_add(4), param0);
+    write_word(command.wrapping_add(8), param1);
+    write_word(command.wrapping_add(12), param2);
+    write_word(command.wrapping_add(16), param3);
+    let result = core::ptr::addr_of_mut!(IAP_RESULT) as u32;
+    iap_entry(command as *mut u32, result as *mut u32);
+    read_word(result)
+}
+
+#[inline(always)]
+#[link_section = ".boot.text"]
+unsafe fn read_byte(addr: u32) -> u32 {
+    let byte: u32;
+    core::arch::asm!("ldrb {byte}, [{addr}]", addr = in(reg) addr, byte = lateout(reg) byte);
+    byte
+}
+
+#[inline(always)]
+#[link_section = ".boot.text"]
+unsafe fn read_word(addr: u32) -> u32 {
+    let word: u32;
+    core::arch::asm!("ldr {word}, [{addr}]", addr = in(reg) addr, word = lateout(reg) word);
+    word
+}
+
+#[inline(always)]
+#[link_section = ".boot.text"]
+unsafe fn write_word(addr: u32, word: u32) {
+    core::arch::asm!("str {word}, [{addr}]", addr = in(reg) addr, word = in(reg) word);
+}
+
+#[link_section = ".boot.text"]
+unsafe fn is_valid_image(length: u32, crc: u32) -> bool {
+    //! The checks of `Updater::verify` once more, the request alone is not enough to erase the
+    //! firmware. The CRC is the one of zlib, computed bit by bit to keep the bootloader small.
+    if length < FIRST_CODE_ADDR || length > APPLICATION_SIZE {
+        return false;
+    }
+    let mut checksum = 0xFFFF_FFFF_u32;
+    let mut offset = 0_u32;
+    while offset < length {
+        checksum ^= read_byte(STAGING_ADDR.wrapping_add(offset));
+        let mut bit = 0_u32;
+        while bit < 8 {
+            checksum = if checksum & 1 == 1 {
+                (checksum >> 1) ^ 0xEDB8_8320
+            } else {
+                checksum >> 1
+            };
+            bit = bit.wrapping_add(1);
+        }
+        offset = offset.wrapping_add(1);
+    }
+    let vector_table = STAGING_ADDR.wrapping_add(APPLICATION_ADDR);
+    let stack_pointer = read_word(vector_table);
+    let reset_vector = read_word(vector_table.wrapping_add(4)) & !1;
+    !checksum == crc
+        && stack_pointer >= RAM_START
+        && stack_pointer <= RAM_END
+        && reset_vector >= FIRST_CODE_ADDR
+        && reset_vector < length
+}
+
+#[link_section = ".boot.text"]
+unsafe fn install(length: u32) -> bool {
+    //! Erases the firmware sectors and copies the image behind sector 0 over them page by page
+    if iap(50, FIRST_APPLICATION_SECTOR, LAST_APPLICATION_SECTOR, 0, 0) != CMD_SUCCESS {
+        return false;
+    }
+    if iap(
+        52,
+        FIRST_APPLICATION_SECTOR,
+        LAST_APPLICATION_SECTOR,
+        CCLK_KHZ,
+        0,
+    ) != CMD_SUCCESS
+    {
+        return false;
+    }
+    let buffer = core::ptr::addr_of_mut!(PAGE_BUFFER) as u32;
+    let mut offset = APPLICATION_ADDR;
+    while offset < length {
+        let mut word = 0_u32;
+        while word < PAGE_LEN {
+            let source = STAGING_ADDR.wrapping_add(offset).wrapping_add(word);
+            write_word(buffer.wrapping_add(word), read_word(source));
+            word = word.wrapping_add(4);
+        }
+        if iap(50, FIRST_APPLICATION_SECTOR, LAST_APPLICATION_SECTOR, 0, 0) != CMD_SUCCESS {
+            return false;
+        }
+        if iap(51, offset, buffer, PAGE_LEN, CCLK_KHZ) != CMD_SUCCESS {
+            return false;
+        }
+        offset = offset.wrapping_add(PAGE_LEN);
+    }
+    true
+}
+
+#[link_section = ".boot.text"]
+unsafe fn start_firmware() -> ! {
+    //! Points VTOR at the vector table of the firmware, then loads its stack pointer and jumps
+    //! to its reset handler
+    core::arch::asm!(
+        "str r0, [r1]",
+        "ldr r2, [r0]",
+        "msr msp, r2",
+        "ldr r2, [r0, #4]",
+        "bx r2",
+        in("r0") APPLICATION_ADDR,
+        in("r1") VTOR,
+        options(noreturn),
+    )
+}
@@ -0,0 +1,67 @@
+use crate::setup;
+
+/// Half of an SCL period while recovering, 5µs at 48MHz for 100kHz
+const HALF_PERIOD_CYCLES: u32 = 240;
+/// A slave can be stuck in the middle of a byte plus its ACK at most
+const MAX_CLOCK_PULSES: u8 = 9;
+
+const SCL_PIN: u32 = 4;
+const SDA_PIN: u32 = 5;
+
+fn is_sda_high(gpio: &lpc11u6x_pac::GPIO_PORT) -> bool {
+    gpio.pin[0].read().bits() & (1 << SDA_PIN) != 0
+}
+
+fn pull_low(gpio: &lpc11u6x_pac::GPIO_PORT, pin: u32) {
+    // the output latch stays at 0, switching to output pulls the line low
+    gpio.dir[0].modify(|r, w| unsafe { w.bits(r.bits() | (1 << pin)) });
+}
+
+fn release(gpio: &lpc11u6x_pac::GPIO_PORT, pin: u32) {
+    gpio.dir[0].modify(|r, w| unsafe { w.bits(r.bits() & !(1 << pin)) });
+}
+
+fn half_period() {
+    cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+}
+
+pub fn recover_i2c0(
+    iocon: &lpc11u6x_pac::IOCON,
+    gpio: &lpc11u6x_pac::GPIO_PORT,
+    i2c0: &lpc11u6x_pac::I2C0,
+) -> bool {
+    //! Clocks SCL until a slave holding SDA low lets go, then sends a STOP.
+    //!
+    //! Returns whether SDA is released afterwards.
+    i2c0.conclr.write(|w| unsafe { w.bits(1 << 3 | 1 << 5) });
+
+    setup::select_i2c0_gpio_function(iocon);
+    release(gpio, SCL_PIN);
+    release(gpio, SDA_PIN);
+    half_period();
+
+    for _ in 0..MAX_CLOCK_PULSES {
+        if is_sda_high(gpio) {
+            break;
+        }
+        pull_low(gpio, SCL_PIN);
+        half_period();
+        release(gpio, SCL_PIN);
+        half_period();
+    }
+
+    // STOP: SDA rising while SCL is high
+    pull_low(gpio, SCL_PIN);
+    half_period();
+    pull_low(gpio, SDA_PIN);
+    half_period();
+    release(gpio, SCL_PIN);
+    half_period();
+    release(gpio, SDA_PIN);
+    half_period();
+
+    let released = is_sda_high(gpio);
+    setup::select_i2c0_pin_function(iocon);
+    i2c0.conset.write(|w| w.sto().set_bit());
+    released
+}
@@ -1,4 +1,5 @@
 use crate::clock;
+use crate::journal::WriteResult;
 use crate::rom::uart::*;
 use crate::uart0;
 
@@ -124,7 +125,12 @@ impl Dbg {
         self.println(msg.as_ptr(), msg.len() as u32);
     }
 
-    pub fn debug_number(&self, mut num: u32) {
+    pub fn debug_number(&self, num: u32) {
+        self.print_decimal(num);
+        self.newline();
+    }
+
+    pub fn print_decimal(&self, mut num: u32) {
         let mut buffer = [0_u8; 32];
         let mut index = 31;
         while num >= 10 {
@@ -133,7 +139,39 @@ impl Dbg {
             index -= 1;
         }
         buffer[index] = num as u8 + 48;
-        self.println(buffer[index..].as_ptr(), buffer[index..].len() as u32);
+        self.print(buffer[index..].as_ptr(), buffer[index..].len() as u32);
+    }
+
+    pub fn debug_journal_entry(&self, entry: &crate::journal::Entry) {
+        //! Uptime in ms, register, data bytes as written (truncated ones are marked with ..) and
+        //! the result, all on one line
+        self.print_decimal(entry.timestamp_ms);
+        self.print_raw_byte(32);
+        self.print_hex_byte(entry.register);
+        for byte in entry.data() {
+            self.print_raw_byte(32);
+            self.print_hex_byte(*byte);
+        }
+        if entry.data().len() < entry.len as usize {
+            self.print(" ..".as_ptr(), 3);
+        }
+        let result = match entry.result {
+            WriteResult::Forwarded => " OK",
+            WriteResult::NotAcknowledged => " NACK",
+            WriteResult::Denied => " DENIED",
+            WriteResult::PecError => " PEC",
+        };
+        self.println(result.as_ptr(), result.len() as u32);
+    }
+
+    pub fn debug_settings_export(&self, export: &[u8]) {
+        //! One line of hex bytes, in the order they are written to the import command
+        self.print("Settings export:".as_ptr(), 16);
+        for byte in export {
+            self.print_raw_byte(32);
+            self.print_hex_byte(*byte);
+        }
+        self.newline();
     }
 
     pub fn debug_eeprom(&self, sys: &lpc11u6x_pac::SYSCON) {
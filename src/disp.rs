@@ -44,6 +44,7 @@ pub enum Character {
     D,
     E,
     F,
+    P,
 }
 
 impl From<Character> for u8 {
@@ -67,6 +68,7 @@ impl From<Character> for u8 {
             Character::D => 94,
             Character::E => 121,
             Character::F => 113,
+            Character::P => 115,
         }
     }
 }
@@ -109,6 +111,28 @@ pub const fn temp_into_row(data: u8) -> [Character; 4] {
     [d3, d2, d1, d0]
 }
 
+pub const fn power_into_row(data: u16) -> [Character; 4] {
+    if data < 1000 {
+        let d3 = digit_into_character((data / 100) as u8);
+        let d2 = digit_into_character(((data % 100) / 10) as u8);
+        let d1 = digit_into_character((data % 10) as u8);
+        [d3, d2, d1, Character::P]
+    } else {
+        [Character::F; 4]
+    }
+}
+
+pub const fn energy_into_row(data: u32) -> [Character; 4] {
+    if data < 1000 {
+        let d3 = digit_into_character((data / 100) as u8);
+        let d2 = digit_into_character(((data % 100) / 10) as u8);
+        let d1 = digit_into_character((data % 10) as u8);
+        [d3, d2, d1, Character::E]
+    } else {
+        [Character::F; 4]
+    }
+}
+
 pub const fn voltage_into_row(data: f32) -> [Character; 4] {
     let mv = data as i32;
     if mv >= 0 && mv < 10000 {
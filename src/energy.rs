@@ -0,0 +1,67 @@
+use crate::vrm::Loop;
+
+const MICROJOULE_PER_MILLIWATT_HOUR: u64 = 3_600_000;
+
+pub fn power_mw(voltage_mv: f32, current_a: u16) -> u32 {
+    (voltage_mv * current_a as f32) as u32
+}
+
+/// Integrates the output power of both loops over time
+pub struct EnergyMeter {
+    energy_uj: [u64; 2],
+    elapsed_ms: u64,
+    last_sample_ms: Option<u32>,
+}
+
+impl EnergyMeter {
+    pub fn new() -> Self {
+        EnergyMeter {
+            energy_uj: [0; 2],
+            elapsed_ms: 0,
+            last_sample_ms: None,
+        }
+    }
+
+    pub fn reset(&mut self, now_ms: u32) {
+        self.energy_uj = [0; 2];
+        self.elapsed_ms = 0;
+        self.last_sample_ms = Some(now_ms);
+    }
+
+    pub fn add_sample(&mut self, now_ms: u32, l1_power_mw: u32, l2_power_mw: u32) {
+        //! The power of each sample is assumed to be constant since the previous sample
+        if let Some(last_sample_ms) = self.last_sample_ms {
+            let delta_ms = now_ms.wrapping_sub(last_sample_ms) as u64;
+            self.energy_uj[0] += l1_power_mw as u64 * delta_ms;
+            self.energy_uj[1] += l2_power_mw as u64 * delta_ms;
+            self.elapsed_ms += delta_ms;
+        }
+        self.last_sample_ms = Some(now_ms);
+    }
+
+    pub fn energy_mwh(&self, output: Loop) -> u32 {
+        (self.energy_uj[output as usize] / MICROJOULE_PER_MILLIWATT_HOUR) as u32
+    }
+
+    pub fn energy_wh(&self, output: Loop) -> u32 {
+        self.energy_mwh(output) / 1000
+    }
+
+    pub fn average_power_w(&self, output: Loop) -> u16 {
+        if self.elapsed_ms == 0 {
+            return 0;
+        }
+        // µJ per ms is mW
+        (self.energy_uj[output as usize] / self.elapsed_ms / 1000) as u16
+    }
+
+    pub fn session_duration_s(&self) -> u32 {
+        (self.elapsed_ms / 1000) as u32
+    }
+}
+
+impl Default for EnergyMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,126 @@
+use crate::telemetry::{Snapshot, ADC_CHANNEL_COUNT};
+use crate::vrm::Loop;
+
+/// Number of samples kept, at one sample per second this covers the last few minutes
+pub const HISTORY_LENGTH: usize = 256;
+/// Size of a sample when read out over I2C
+pub const SAMPLE_SIZE: usize = 24;
+
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+    timestamp_ms: u32,
+    voltage_mv: [u16; 2],
+    current_a: [u16; 2],
+    temp_c: [u8; 2],
+    adc_mv: [u16; ADC_CHANNEL_COUNT],
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry {
+        timestamp_ms: 0,
+        voltage_mv: [0; 2],
+        current_a: [0; 2],
+        temp_c: [0; 2],
+        adc_mv: [0; ADC_CHANNEL_COUNT],
+    };
+
+    fn byte(&self, index: usize) -> u8 {
+        //! Uptime in ms, then voltage (mV), current (A) and temperature (°C) of L1 and L2,
+        //! then the ADC rails in the order of the monitoring registers. All values MSB first.
+        match index {
+            0..=3 => self.timestamp_ms.to_be_bytes()[index],
+            4..=13 => {
+                let output = (index - 4) / 5;
+                match (index - 4) % 5 {
+                    offset @ 0..=1 => self.voltage_mv[output].to_be_bytes()[offset],
+                    offset @ 2..=3 => self.current_a[output].to_be_bytes()[offset - 2],
+                    _ => self.temp_c[output],
+                }
+            }
+            _ => self.adc_mv[(index - 14) / 2].to_be_bytes()[(index - 14) % 2],
+        }
+    }
+}
+
+impl From<&Snapshot> for Entry {
+    fn from(snapshot: &Snapshot) -> Self {
+        let l1 = snapshot.output(Loop::L1);
+        let l2 = snapshot.output(Loop::L2);
+        Entry {
+            timestamp_ms: snapshot.timestamp_ms,
+            voltage_mv: [l1.voltage_mv as u16, l2.voltage_mv as u16],
+            current_a: [l1.current_a, l2.current_a],
+            temp_c: [l1.temp_c, l2.temp_c],
+            adc_mv: snapshot.adc_mv,
+        }
+    }
+}
+
+/// Ring buffer of telemetry samples that can be read out after the fact
+pub struct History {
+    entries: [Entry; HISTORY_LENGTH],
+    next: usize,
+    len: usize,
+    paused: bool,
+}
+
+impl History {
+    pub const fn new() -> Self {
+        //! `const` so that the ring can be placed in a static instead of being built on the stack
+        History {
+            entries: [Entry::EMPTY; HISTORY_LENGTH],
+            next: 0,
+            len: 0,
+            paused: false,
+        }
+    }
+
+    pub fn record(&mut self, snapshot: &Snapshot) {
+        if self.paused {
+            return;
+        }
+        self.entries[self.next] = snapshot.into();
+        self.next = (self.next + 1) % HISTORY_LENGTH;
+        if self.len < HISTORY_LENGTH {
+            self.len += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+
+    pub fn byte(&self, index: usize) -> Option<u8> {
+        //! Byte `index` of all samples laid out back to back, starting with the oldest sample
+        let sample = index / SAMPLE_SIZE;
+        if sample >= self.len {
+            return None;
+        }
+        let oldest = (self.next + HISTORY_LENGTH - self.len) % HISTORY_LENGTH;
+        let entry = &self.entries[(oldest + sample) % HISTORY_LENGTH];
+        Some(entry.byte(index % SAMPLE_SIZE))
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,34 +1,37 @@
 use crate::disp;
-use core::hint::unreachable_unchecked;
+use crate::energy::EnergyMeter;
+use crate::history::{History, SAMPLE_SIZE};
+use crate::journal::{self, Journal};
+use crate::proxy::Proxy;
+use crate::sweep::{Sweep, SweepConfig};
+use crate::telemetry::Snapshot;
+use crate::update::Updater;
+use crate::vrm::{self, Loop};
+use cortex_m::peripheral::NVIC;
+use epower_protocol::evbot::{
+    Board, Evbot, EvbotState, LoopMonitoring, Monitoring, ReadEvent, Stream, WriteResult,
+    RECIEVE_BUFFER_SIZE,
+};
+use epower_protocol::settings::{EXPORT_SIZE, PRESET_NAMES_SIZE, PRESET_NAME_SIZE};
+use epower_protocol::{Action, SlaveMachine};
+use lpc11u6x_pac::Interrupt;
 
+/// Address of the IR3595 on I2C0, also the default address of the proxy on the EVBot connector
 pub(crate) const IR3595_ADDR: u8 = 0x08;
 pub(crate) const IR3595_ADDR_SHIFTED: u8 = IR3595_ADDR << 1;
+/// Default address of the Epower on the EVBot connector
 pub(crate) const EPOWER_ADDR: u8 = 0x0E;
-pub(crate) const EPOWER_ADDR_SHIFTED: u8 = EPOWER_ADDR << 1;
 
-mod i2c_driver_states {
-    /// Own SLA+W has been received; ACK has been returned.
-    pub const SLAVE_WRITE_RECIEVED_AND_ACKED: u32 = 0x60;
-
-    // Previously addressed with own SLV address; DATA has been received; ACK has been returned
-    pub const RECIEVED_DATA_AND_ACKED: u32 = 0x80;
-
-    // A STOP condition or Repeated START condition has been received while still addressed as SLV/REC or SLV/TRX
-    pub const RECIEVED_STOP_OR_REPEAT: u32 = 0xA0;
-
-    // Own SLA+R has been received; ACK has been returned.
-    pub const SLAVE_READ_RECIEVED_AND_ACKED: u32 = 0xA8;
-
-    // Data byte in DAT has been transmitted; ACK has been received.
-    pub const SEND_DATA_AND_RECIEVED_ACK: u32 = 0xB8;
-
-    // Data byte in DAT has been transmitted; NOT ACK has been received.
-    pub const SEND_DATA_AND_RECIEVED_NOT_ACK: u32 = 0xC0;
+pub fn set_slave_addresses(periph: &lpc11u6x_pac::I2C1, settings: &crate::settings::Settings) {
+    //! Address 0 is the general call, which is never enabled, so it disables the slave address
+    let proxy_addr = settings.proxy_address().unwrap_or(0);
+    let epower_addr = settings.epower_address().unwrap_or(0);
+    unsafe { periph.adr0.write(|w| w.address().bits(proxy_addr)) };
+    unsafe { periph.adr1().write(|w| w.address().bits(epower_addr)) };
 }
 
-pub fn init_slave_recv(periph: &lpc11u6x_pac::I2C1) {
-    unsafe { periph.adr0.write(|w| w.address().bits(IR3595_ADDR)) };
-    unsafe { periph.adr1().write(|w| w.address().bits(EPOWER_ADDR)) };
+pub fn init_slave_recv(periph: &lpc11u6x_pac::I2C1, settings: &crate::settings::Settings) {
+    set_slave_addresses(periph, settings);
     periph.conset.write(|w| {
         w.i2en()
             .set_bit()
@@ -43,272 +46,437 @@ pub fn init_slave_recv(periph: &lpc11u6x_pac::I2C1) {
     });
 }
 
-enum I2C1Mode {
-    EpowerRead,
-    EpowerWrite,
-    Ir3595Read,
-    IR3595Write,
-    Idle,
+/// Commands that end the running firmware
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Restart {
+    Reset,
+    Isp,
 }
 
 pub struct I2C1State {
     periph: lpc11u6x_pac::I2C1,
-    recieve_buffer: [u8; 8],
-    recieve_buffer_index: usize,
-    mode: I2C1Mode,
-    send_buffer: [u8; 4],
-    send_buffer_index: usize,
+    machine: SlaveMachine<RECIEVE_BUFFER_SIZE>,
+    state: EvbotState,
+    proxy: Proxy,
+    journal: Journal,
+    updater: Updater,
+    /// The host asked for the settings to be printed over UART
+    export_requested: bool,
+    /// Left to the main loop, which stores pending settings first
+    restart: Option<Restart>,
+}
+
+/// Everything behind the EVBot registers for one interrupt
+struct Hardware<'a> {
+    periph: &'a lpc11u6x_pac::I2C1,
+    settings: &'a mut crate::settings::Settings,
+    vrm_i2c_driver: &'a crate::rom::i2c::I2CDriver,
+    i2c0: &'a lpc11u6x_pac::I2C0,
+    display: &'a mut disp::Display,
+    snapshot: &'a Snapshot,
+    gpio: &'a lpc11u6x_pac::GPIO_PORT,
+    sweep: &'a mut Sweep,
+    energy: &'a mut EnergyMeter,
+    history: &'a mut History,
+    proxy: &'a mut Proxy,
+    journal: &'a mut Journal,
+    updater: &'a mut Updater,
+    export_requested: &'a mut bool,
+    restart: &'a mut Option<Restart>,
+    uptime_ms: u32,
+}
+
+impl Hardware<'_> {
+    fn settings_changed(&self) {
+        //! Changed addresses are answered from the next transaction on
+        set_slave_addresses(self.periph, self.settings);
+    }
+
+    fn loop_monitoring(&self, output: Loop) -> LoopMonitoring {
+        let telemetry = self.snapshot.output(output);
+        LoopMonitoring {
+            enabled: self.snapshot.enabled[output as usize],
+            power_good: self.snapshot.power_good[output as usize],
+            voltage_mv: telemetry.voltage_mv,
+            current_a: telemetry.current_a,
+            power_mw: telemetry.power_mw(),
+            temp_c: telemetry.temp_c,
+            energy_mwh: self.energy.energy_mwh(output),
+            average_power_w: self.energy.average_power_w(output),
+        }
+    }
+}
+
+/// The values handed over by `Evbot` are already checked, rejected ones never get here
+impl Board for Hardware<'_> {
+    fn setting(&self, setting_id: u8) -> u8 {
+        self.settings.get_setting_value(setting_id)
+    }
+
+    fn write_settings(&mut self, first_setting_id: u8, values: &[u8]) {
+        let _ = self
+            .settings
+            .set_setting_values(first_setting_id, values, self.display, self.gpio);
+        self.settings_changed();
+    }
+
+    fn has_uncommitted_settings(&self) -> bool {
+        self.settings.has_uncommitted_changes()
+    }
+
+    fn commit_settings(&mut self) {
+        self.settings.commit();
+    }
+
+    fn discard_settings(&mut self) {
+        self.settings
+            .discard(self.display, self.vrm_i2c_driver, self.gpio);
+        self.settings_changed();
+    }
+
+    fn restore_default_settings(&mut self) {
+        self.settings
+            .restore_defaults(self.display, self.vrm_i2c_driver, self.gpio);
+        self.settings_changed();
+    }
+
+    fn export_settings(&self) -> [u8; EXPORT_SIZE] {
+        self.settings.export()
+    }
+
+    fn import_settings(&mut self, settings: &[u8; 60], names: &[u8; PRESET_NAMES_SIZE]) {
+        self.settings.import(
+            settings,
+            names,
+            self.display,
+            self.vrm_i2c_driver,
+            self.gpio,
+        );
+        self.settings_changed();
+    }
+
+    fn print_settings_export(&mut self) {
+        *self.export_requested = true;
+    }
+
+    fn preset_name(&self, number: u8) -> [u8; PRESET_NAME_SIZE] {
+        self.settings.preset_name(number).unwrap_or_default()
+    }
+
+    fn set_preset_name(&mut self, number: u8, name: &[u8]) {
+        let _ = self.settings.set_preset_name(number, name);
+    }
+
+    fn active_preset(&self) -> u8 {
+        self.settings.active_preset()
+    }
+
+    fn select_preset(&mut self, number: u8) {
+        self.settings
+            .select_preset(number, self.vrm_i2c_driver, self.gpio);
+    }
+
+    fn boot_count(&self) -> u32 {
+        self.settings.boot_count()
+    }
+
+    fn epower_address(&self) -> Option<u8> {
+        self.settings.epower_address()
+    }
+
+    fn proxy_address(&self) -> Option<u8> {
+        self.settings.proxy_address()
+    }
+
+    fn monitoring(&self) -> Monitoring {
+        Monitoring {
+            timestamp_ms: self.snapshot.timestamp_ms,
+            adc_mv: self.snapshot.adc_mv,
+            faults: self.snapshot.faults,
+            loops: [
+                self.loop_monitoring(Loop::L1),
+                self.loop_monitoring(Loop::L2),
+            ],
+            session_duration_s: self.energy.session_duration_s(),
+        }
+    }
+
+    fn is_loop_enabled(&self, index: usize) -> bool {
+        let output = if index == 0 { Loop::L1 } else { Loop::L2 };
+        vrm::is_enabled(self.gpio, output)
+    }
+
+    fn reset_energy(&mut self) {
+        self.energy.reset(self.uptime_ms);
+    }
+
+    fn configure_sweep(&mut self, config: &[u8]) {
+        if let Some(config) = SweepConfig::from_bytes(config) {
+            self.sweep.configure(config);
+        }
+    }
+
+    fn start_sweep(&mut self) {
+        self.sweep.start(self.vrm_i2c_driver, self.uptime_ms);
+    }
+
+    fn abort_sweep(&mut self) {
+        self.sweep.abort(self.vrm_i2c_driver);
+    }
+
+    fn sweep_state(&self) -> u8 {
+        self.sweep.state().into()
+    }
+
+    fn sweep_record_count(&self) -> u8 {
+        self.sweep.record_count() as u8
+    }
+
+    fn history_len(&self) -> u16 {
+        self.history.len() as u16
+    }
+
+    fn is_history_paused(&self) -> bool {
+        self.history.is_paused()
+    }
+
+    fn set_history_paused(&mut self, paused: bool) {
+        self.history.set_paused(paused);
+    }
+
+    fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    fn journal_len(&self) -> u16 {
+        self.journal.len() as u16
+    }
+
+    fn is_journal_paused(&self) -> bool {
+        self.journal.is_paused()
+    }
+
+    fn set_journal_paused(&mut self, paused: bool) {
+        self.journal.set_paused(paused);
+    }
+
+    fn clear_journal(&mut self) {
+        self.journal.clear();
+    }
+
+    fn request_journal_dump(&mut self) {
+        self.journal.request_dump();
+    }
+
+    fn stream_byte(&self, stream: Stream, start: usize, index: usize) -> Option<u8> {
+        match stream {
+            Stream::SweepRecords => self.sweep.record_byte(index),
+            Stream::History => self.history.byte(start * SAMPLE_SIZE + index),
+            Stream::Journal => self.journal.byte(start * journal::ENTRY_SIZE + index),
+        }
+    }
+
+    fn update_state(&self) -> u8 {
+        self.updater.state().into()
+    }
+
+    fn next_update_block(&self) -> u16 {
+        self.updater.next_block()
+    }
+
+    fn start_update(&mut self, length: u32, crc: u32) {
+        self.updater.start(length, crc);
+    }
+
+    fn write_update_block(&mut self, block: u16, data: &[u8]) {
+        self.updater.write_block(block, data);
+    }
+
+    fn install_update(&mut self) {
+        self.updater.request_install();
+    }
+
+    fn reset(&mut self) {
+        *self.restart = Some(Restart::Reset);
+    }
+
+    fn enter_isp(&mut self) {
+        *self.restart = Some(Restart::Isp);
+    }
+
+    fn settle_proxy(&mut self) {
+        self.proxy.settle(self.i2c0, self.uptime_ms, self.journal);
+    }
+
+    fn set_pending_ir3595_cmd(&mut self, cmd: u8, writable: bool) {
+        self.proxy.set_pending_cmd(cmd, writable);
+    }
+
+    fn pending_ir3595_cmd(&self) -> Option<u8> {
+        self.proxy.pending_cmd()
+    }
+
+    fn discard_pending_ir3595_cmd(&mut self) {
+        self.proxy.discard_pending_cmd();
+    }
+
+    fn write_ir3595(&mut self, data: &[u8]) {
+        self.proxy
+            .write(self.i2c0, data, self.uptime_ms, self.journal);
+    }
+
+    fn start_ir3595_read(&mut self, single: bool) {
+        self.proxy.start_read(self.i2c0, self.uptime_ms, single);
+    }
+
+    fn next_ir3595_read(&mut self) -> bool {
+        self.proxy.read_next(self.i2c0)
+    }
+
+    fn finish_ir3595_read(&mut self) {
+        self.proxy.finish_read(self.i2c0);
+    }
+
+    fn record_ir3595_write(&mut self, write: &[u8], result: WriteResult) {
+        self.journal.record(self.uptime_ms, write, result);
+    }
 }
 
 impl I2C1State {
     pub fn new(i2c1: lpc11u6x_pac::I2C1) -> Self {
         I2C1State {
             periph: i2c1,
-            recieve_buffer: [0; 8],
-            recieve_buffer_index: 0,
-            mode: I2C1Mode::Idle,
-            send_buffer: [0u8; 4],
-            send_buffer_index: 0,
+            machine: SlaveMachine::new(),
+            // read once at startup, the IAP routines are not called from the interrupt handlers
+            state: EvbotState::new(crate::FIRMWARE_VERSION, crate::rom::eeprom::get_uid()),
+            proxy: Proxy::new(),
+            journal: Journal::new(),
+            updater: Updater::new(),
+            export_requested: false,
+            restart: None,
         }
     }
 
-    fn monitoring_read(&mut self, cmd: u8, adc: &crate::adc::Adc) {
-        let channel = match cmd {
-            0 => 2,
-            1 => 1,
-            2 => 8,
-            3 => 10,
-            4 => 9,
-            _ => {
-                return;
-            }
-        };
-        unsafe {
-            let (higher, lower) = adc.voltage_bytes(channel);
-            self.send_buffer[0] = lower;
-            self.periph.dat.write(|w| w.bits(higher as u32));
-        }
-        self.recieve_buffer_index = 1;
-    }
-
-    fn constants_read(&mut self, cmd: u8) {
-        match cmd {
-            1 => unsafe {
-                self.periph
-                    .dat
-                    .write(|w| w.bits(crate::FIRMWARE_VERSION as u32))
-            },
-            2 => {
-                let uid = crate::rom::eeprom::get_uid().to_be_bytes();
-                self.send_buffer = uid;
-                self.send_buffer_index = 2;
-                unsafe {
-                    self.periph
-                        .dat
-                        .write(|w| w.bits(self.send_buffer[3] as u32))
-                };
-            }
-            _ => {}
-        }
+    pub fn is_proxy_active(&self) -> bool {
+        self.proxy.is_active()
     }
 
-    fn settings_read(&mut self, cmd: u8, settings: &crate::settings::Settings) {
-        let value = settings.get_setting_value(cmd);
-        unsafe { self.periph.dat.write(|w| w.bits(value as u32)) }
+    pub fn record_sample(&mut self, snapshot: &Snapshot, bus_recoveries: u16) {
+        //! Keeps the status registers up to date with every telemetry sample
+        self.state.record_sample(snapshot.faults, bus_recoveries);
     }
 
-    fn settings_write(
-        &mut self,
-        settings: &mut crate::settings::Settings,
-        display: &mut disp::Display,
-        gpio: &lpc11u6x_pac::GPIO_PORT,
-    ) {
-        let cmd = self.recieve_buffer[0];
-        let value = self.recieve_buffer[1];
-        if (64..128).contains(&cmd) {
-            let setting_id = cmd - 64;
-            settings.set_setting_value(setting_id, value, display, gpio);
-        }
+    pub fn journal_mut(&mut self) -> &mut Journal {
+        &mut self.journal
+    }
 
-        self.recieve_buffer_index = 0;
+    pub fn take_export_request(&mut self) -> bool {
+        //! Whether the settings should be printed over UART, only true once per request
+        core::mem::take(&mut self.export_requested)
     }
 
-    fn handle_epower_read(&mut self, settings: &crate::settings::Settings, adc: &crate::adc::Adc) {
-        if self.send_buffer_index > 0 {
-            unsafe {
-                self.periph
-                    .dat
-                    .write(|w| w.bits(self.send_buffer[self.send_buffer_index] as u32))
+    pub fn service(&mut self, settings: &mut crate::settings::Settings) {
+        //! Called from the main loop in a critical section, for the work that needs the IAP
+        //! routines: the flash work of a firmware update (see `Updater::service`), installing it,
+        //! resets and entering ISP. The last three store pending settings first.
+        let updater = &mut self.updater;
+        updater.service(settings.syscon());
+        if updater.take_install_request() {
+            settings.flush();
+            updater.install(settings.syscon());
+        }
+        match self.restart.take() {
+            Some(Restart::Reset) => {
+                settings.flush();
+                cortex_m::peripheral::SCB::sys_reset()
             }
-            self.send_buffer_index -= 1;
-        } else if self.recieve_buffer_index == 1 {
-            let cmd = self.recieve_buffer[0];
-            match cmd {
-                0..=63 => {
-                    self.constants_read(cmd);
-                }
-                64..=127 => {
-                    self.settings_read(cmd - 64, settings);
-                }
-                128..=250 => {
-                    self.monitoring_read(cmd - 128, adc);
-                }
-                251..=255 => {
-                    unsafe { self.periph.dat.write(|w| w.bits(0xEE_u32)) };
-                }
+            Some(Restart::Isp) => {
+                settings.flush();
+                crate::rom::eeprom::reinvoke_isp()
             }
-            self.recieve_buffer_index = 0;
-        } else {
-            unsafe { self.periph.dat.write(|w| w.bits(0xEE_u32)) };
+            None => {}
+        }
+    }
+
+    pub fn service_proxy(&mut self, i2c0: &lpc11u6x_pac::I2C0, now_ms: u32) {
+        if let Some(event) = self.proxy.service(i2c0, now_ms, &mut self.journal) {
+            self.send_proxied_byte(event);
+        }
+    }
+
+    pub fn handle_proxy_interrupt(&mut self, i2c0: &lpc11u6x_pac::I2C0, now_ms: u32) {
+        if let Some(event) = self.proxy.handle_interrupt(i2c0, now_ms, &mut self.journal) {
+            self.send_proxied_byte(event);
         }
+    }
 
-        self.periph.conset.write(|w| w.aa().set_bit());
+    fn send_proxied_byte(&mut self, event: ReadEvent) {
+        //! Ends the clock stretching of a proxied read, see `Action::stretch`
+        let value = self.state.ir3595_read_done(self.proxy.read_cmd(), event);
+        self.apply(Action::send(value));
+        NVIC::unpend(Interrupt::I2C1);
+        unsafe { NVIC::unmask(Interrupt::I2C1) };
     }
 
-    fn ack_and_clear_int(&self) {
-        self.periph.conset.write(|w| w.aa().set_bit());
-        self.periph.conclr.write(|w| w.sic().set_bit());
+    fn apply(&self, action: Action) {
+        if let Some(value) = action.data {
+            unsafe { self.periph.dat.write(|w| w.bits(value as u32)) };
+        }
+        if action.ack || action.stop {
+            self.periph
+                .conset
+                .write(|w| w.aa().bit(action.ack).sto().bit(action.stop));
+        }
+        if action.clear_interrupt || action.clear_start {
+            self.periph.conclr.write(|w| {
+                w.sic()
+                    .bit(action.clear_interrupt)
+                    .stac()
+                    .bit(action.clear_start)
+            });
+        }
+        // SI stays set until the byte is there, which would raise the interrupt over and over
+        if action.stretch {
+            NVIC::mask(Interrupt::I2C1);
+        }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn handle_state(
         &mut self,
         settings: &mut crate::settings::Settings,
         vrm_i2c_driver: &crate::rom::i2c::I2CDriver,
+        i2c0: &lpc11u6x_pac::I2C0,
         display: &mut disp::Display,
-        adc: &crate::adc::Adc,
+        snapshot: &Snapshot,
         gpio: &lpc11u6x_pac::GPIO_PORT,
+        sweep: &mut Sweep,
+        energy: &mut EnergyMeter,
+        history: &mut History,
+        uptime_ms: u32,
     ) {
         let stat = self.periph.stat.read().bits();
-
-        match stat {
-            i2c_driver_states::SLAVE_WRITE_RECIEVED_AND_ACKED => {
-                let raw_addr_byte = self.periph.dat.read().bits() as u8;
-                let addr = raw_addr_byte >> 1;
-                self.mode = match addr {
-                    0x08 => I2C1Mode::IR3595Write,
-                    0x0E => I2C1Mode::EpowerWrite,
-                    _ => I2C1Mode::Idle,
-                };
-                self.recieve_buffer_index = 0;
-                self.ack_and_clear_int();
-            }
-
-            i2c_driver_states::RECIEVED_DATA_AND_ACKED => match self.mode {
-                I2C1Mode::EpowerWrite | I2C1Mode::IR3595Write => {
-                    self.recieve_buffer[self.recieve_buffer_index] =
-                        self.periph.dat.read().bits() as u8;
-                    self.recieve_buffer_index += 1;
-                    self.ack_and_clear_int();
-                }
-                I2C1Mode::Idle | I2C1Mode::Ir3595Read | I2C1Mode::EpowerRead => {
-                    self.periph.conclr.write(|w| w.sic().set_bit());
-                }
-            },
-
-            i2c_driver_states::RECIEVED_STOP_OR_REPEAT => {
-                match self.mode {
-                    I2C1Mode::Ir3595Read | I2C1Mode::EpowerRead => {
-                        self.recieve_buffer_index = 0;
-                        self.send_buffer_index = 0;
-                    }
-                    I2C1Mode::IR3595Write => {
-                        if self.recieve_buffer_index == 2 {
-                            crate::vrm::write_reg(
-                                vrm_i2c_driver,
-                                self.recieve_buffer[0],
-                                self.recieve_buffer[1],
-                            );
-                            self.recieve_buffer_index = 0;
-                            self.send_buffer_index = 0;
-                        }
-                    }
-                    I2C1Mode::EpowerWrite => {
-                        if self.recieve_buffer_index == 2 {
-                            self.settings_write(settings, display, gpio);
-                        }
-                    }
-                    I2C1Mode::Idle => {}
-                }
-                self.mode = I2C1Mode::Idle;
-                self.ack_and_clear_int()
-            }
-
-            i2c_driver_states::SLAVE_READ_RECIEVED_AND_ACKED => {
-                let raw_addr_byte = self.periph.dat.read().bits() as u8;
-                let addr = raw_addr_byte >> 1;
-                self.mode = match addr {
-                    0x08 => I2C1Mode::Ir3595Read,
-                    0x0E => I2C1Mode::EpowerRead,
-                    _ => I2C1Mode::Idle,
-                };
-
-                match self.mode {
-                    I2C1Mode::EpowerRead => {
-                        self.handle_epower_read(settings, adc);
-                    }
-                    I2C1Mode::Ir3595Read => {
-                        if self.recieve_buffer_index == 0 {
-                            unsafe { self.periph.dat.write(|w| w.bits(0xFF_u32)) };
-                        } else if self.recieve_buffer_index == 1 {
-                            if let Some(r) =
-                                crate::vrm::read_reg(vrm_i2c_driver, self.recieve_buffer[0])
-                            {
-                                unsafe { self.periph.dat.write(|w| w.bits(r as u32)) };
-                            }
-
-                            self.recieve_buffer_index = 0;
-                        } else {
-                            self.recieve_buffer_index = 0;
-                        }
-                    }
-                    I2C1Mode::IR3595Write | I2C1Mode::EpowerWrite => unsafe {
-                        unreachable_unchecked()
-                    },
-                    I2C1Mode::Idle => {}
-                }
-                self.ack_and_clear_int();
-            }
-
-            i2c_driver_states::SEND_DATA_AND_RECIEVED_ACK => {
-                match self.mode {
-                    I2C1Mode::Ir3595Read => {
-                        if let Some(value) = crate::vrm::read_raw(vrm_i2c_driver) {
-                            unsafe { self.periph.dat.write(|w| w.bits(value as u32)) };
-                        }
-                    }
-                    I2C1Mode::EpowerRead => {
-                        unsafe {
-                            self.periph
-                                .dat
-                                .write(|w| w.bits(self.send_buffer[self.send_buffer_index] as u32))
-                        };
-                        if self.send_buffer_index > 0 {
-                            self.send_buffer_index -= 1;
-                        }
-                    }
-                    _ => {
-                        unsafe { self.periph.dat.write(|w| w.bits(0xEE)) };
-                    }
-                }
-
-                self.ack_and_clear_int();
-            }
-
-            i2c_driver_states::SEND_DATA_AND_RECIEVED_NOT_ACK => {
-                self.send_buffer_index = 0;
-                self.recieve_buffer_index = 0;
-                self.ack_and_clear_int();
-            }
-            _status_code => {
-                // unimplemented state
-                // TODO: implement arbitration lost states
-                // 0x68
-                self.periph
-                    .conset
-                    .write(|w| w.sta().set_bit().aa().set_bit());
-                self.periph.conclr.write(|w| w.sic().set_bit());
-            }
-        }
-        //self.periph.conclr.write(|w| w.sic().set_bit());
+        let data = self.periph.dat.read().bits() as u8;
+        let mut hardware = Hardware {
+            periph: &self.periph,
+            settings,
+            vrm_i2c_driver,
+            i2c0,
+            display,
+            snapshot,
+            gpio,
+            sweep,
+            energy,
+            history,
+            proxy: &mut self.proxy,
+            journal: &mut self.journal,
+            updater: &mut self.updater,
+            export_requested: &mut self.export_requested,
+            restart: &mut self.restart,
+            uptime_ms,
+        };
+        let mut evbot = Evbot::new(&mut self.state, &mut hardware);
+        let action = self.machine.step(stat, data, &mut evbot);
+        self.apply(action);
     }
 }
@@ -0,0 +1,139 @@
+pub use epower_protocol::evbot::WriteResult;
+
+/// Number of proxied writes kept
+pub const JOURNAL_LENGTH: usize = 64;
+/// Size of an entry when read out over I2C
+pub const ENTRY_SIZE: usize = 11;
+/// Data bytes kept per write, longer writes are truncated
+pub const ENTRY_DATA_SIZE: usize = 4;
+/// Entries printed per telemetry tick while dumping over UART, about 10 ms at 115200 baud
+pub const DUMP_ENTRIES_PER_TICK: usize = 4;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Entry {
+    pub timestamp_ms: u32,
+    pub register: u8,
+    pub result: WriteResult,
+    /// Number of data bytes written, can be more than were kept
+    pub len: u8,
+    pub data: [u8; ENTRY_DATA_SIZE],
+}
+
+impl Entry {
+    pub fn data(&self) -> &[u8] {
+        &self.data[..(self.len as usize).min(ENTRY_DATA_SIZE)]
+    }
+
+    fn byte(&self, index: usize) -> u8 {
+        //! Uptime in ms (MSB first), register, result, data length, then the data padded with 0
+        match index {
+            0..=3 => self.timestamp_ms.to_be_bytes()[index],
+            4 => self.register,
+            5 => self.result.into(),
+            6 => self.len,
+            _ => self.data[index - 7],
+        }
+    }
+}
+
+/// Ring buffer of the writes sent to the IR3595 through the EVBot proxy
+pub struct Journal {
+    entries: [Entry; JOURNAL_LENGTH],
+    next: usize,
+    len: usize,
+    paused: bool,
+    /// Next entry to print over UART while a dump is in progress
+    dump_index: Option<usize>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Journal {
+            entries: [Entry::default(); JOURNAL_LENGTH],
+            next: 0,
+            len: 0,
+            paused: false,
+            dump_index: None,
+        }
+    }
+
+    pub fn record(&mut self, timestamp_ms: u32, write: &[u8], result: WriteResult) {
+        //! `write` is the command byte followed by the data
+        if self.paused {
+            return;
+        }
+        let Some((&register, data)) = write.split_first() else {
+            return;
+        };
+        let mut entry = Entry {
+            timestamp_ms,
+            register,
+            result,
+            len: data.len() as u8,
+            data: [0; ENTRY_DATA_SIZE],
+        };
+        let kept = data.len().min(ENTRY_DATA_SIZE);
+        entry.data[..kept].copy_from_slice(&data[..kept]);
+
+        self.entries[self.next] = entry;
+        self.next = (self.next + 1) % JOURNAL_LENGTH;
+        if self.len < JOURNAL_LENGTH {
+            self.len += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+        self.dump_index = None;
+    }
+
+    fn entry(&self, index: usize) -> Option<&Entry> {
+        //! Entry `index`, starting with the oldest one
+        if index >= self.len {
+            return None;
+        }
+        let oldest = (self.next + JOURNAL_LENGTH - self.len) % JOURNAL_LENGTH;
+        Some(&self.entries[(oldest + index) % JOURNAL_LENGTH])
+    }
+
+    pub fn byte(&self, index: usize) -> Option<u8> {
+        //! Byte `index` of all entries laid out back to back, starting with the oldest entry
+        self.entry(index / ENTRY_SIZE)
+            .map(|entry| entry.byte(index % ENTRY_SIZE))
+    }
+
+    pub fn request_dump(&mut self) {
+        self.dump_index = Some(0);
+    }
+
+    pub fn next_dump_entry(&mut self) -> Option<Entry> {
+        //! The dump is printed a few entries at a time so it does not block everything else
+        let index = self.dump_index?;
+        let entry = self.entry(index).copied();
+        self.dump_index = entry.map(|_| index + 1);
+        entry
+    }
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -16,18 +16,30 @@ use lpc11u6x_pac::{interrupt, Interrupt, NVIC};
 pub const FIRMWARE_VERSION: u8 = 1;
 
 static mut INTERRUPT_CTX: OnceCell<InterruptContext> = OnceCell::new();
+/// About 6K, it would not fit on the stack while `InterruptContext` is built
+static mut HISTORY: history::History = history::History::new();
 
 mod adc;
+mod boot;
+mod bus_recovery;
 mod clock;
 mod dbg;
 mod disp;
+mod energy;
+mod history;
 mod iic;
+mod journal;
 mod pins;
+mod pmbus;
+mod proxy;
 mod rom;
 mod settings;
 mod setup;
+mod sweep;
+mod telemetry;
 mod timer;
 mod uart0;
+mod update;
 mod vrm;
 
 #[entry]
@@ -111,7 +123,7 @@ fn main() -> ! {
             .set_bit()
     }); // reset detection
 
-    let adc = adc::Adc::new(periph.ADC, &sys);
+    let sampler = telemetry::Sampler::new(adc::Adc::new(periph.ADC, &sys));
     
 
     let counter = timer::enable_timer(&sys, timer::TimerSel::CT16B1, periph.CT16B1);
@@ -119,27 +131,30 @@ fn main() -> ! {
 
     let mut display = disp::Display::new(&gpio, &sys, &i2c0, periph.CT16B0);
 
-    let settings = settings::Settings::new(sys);
+    let mut settings = settings::Settings::new(sys);
+    // holding RETURN while powering up makes a misconfigured Epower reachable again
+    if pins::is_return_pressed(&gpio) {
+        settings.reset_addresses();
+    }
 
     display.set_display_orientation(settings.get_display_orientation());
 
     let i2c1 = periph.I2C1;
-    iic::init_slave_recv(&i2c1);
+    iic::init_slave_recv(&i2c1, &settings);
     let i2c1_state = I2C1State::new(i2c1);
 
+    let boot_preset = settings.boot_preset();
     vrm::enable_l2_en(&i2c0);
-    if settings.is_l1_enabled() {
+    if boot_preset.l1_enabled {
         vrm::enable_l1(&gpio);
     }
     vrm::enable_dvid(&i2c0);
-    if settings.is_l2_enabled() {
+    if boot_preset.l2_enabled {
         vrm::enable_l2(&gpio);
     }
-    let l1_voltage_raw = settings.l1_boot_voltage();
-    vrm::set_voltage_l1_raw(&i2c0, l1_voltage_raw);
+    vrm::set_voltage_l1_raw(&i2c0, boot_preset.l1_vid);
 
-    let l2_voltage_raw = settings.l2_boot_voltage();
-    vrm::set_voltage_l2_raw(&i2c0, l2_voltage_raw);
+    vrm::set_voltage_l2_raw(&i2c0, boot_preset.l2_vid);
 
     unsafe {
         INTERRUPT_CTX
@@ -151,9 +166,18 @@ fn main() -> ! {
                 display,
                 i2c1_state,
                 i2c_driver: i2c0,
+                i2c0: periph.I2C0,
+                iocon,
                 settings,
                 gpio,
-                adc,
+                sampler,
+                sweep: sweep::Sweep::new(),
+                energy: energy::EnergyMeter::new(),
+                history: &mut *core::ptr::addr_of_mut!(HISTORY),
+                uptime_ms: 0,
+                next_preset_combo: pins::Button::new(),
+                previous_preset_combo: pins::Button::new(),
+                preset_step: None,
             })
             .map_err(|_| ())
             .expect("Interrupt Context was not set already");
@@ -174,12 +198,19 @@ fn main() -> ! {
     counter.reset();
     counter.int_on_match(2);
     counter.set_prescale(0xFFFF);
-    counter.set_match(2, (freq >> 16) as u16);
+    counter.set_match(2, ((freq >> 16) / telemetry::SAMPLE_RATE_HZ) as u16);
     counter.reset_on_match(2);
     counter.start();
     //unsafe { i2c_proxy::i2c_logic() };
+    // only read for the system clock while the interrupt context owns SYSCON
+    let syscon = unsafe { &*lpc11u6x_pac::SYSCON::ptr() };
     loop {
         cortex_m::asm::wfi();
+        // the interrupt handlers never call the IAP routines, so the EEPROM is written with the
+        // interrupts enabled
+        if let Some(write) = cortex_m::interrupt::free(on_wakeup) {
+            write.write(syscon);
+        }
     }
 }
 
@@ -193,6 +224,7 @@ enum InterruptSource {
     BtnG0,
     Timer16B0,
     Timer16B1,
+    I2C0,
     I2C1,
     Other(i16),
 }
@@ -205,9 +237,48 @@ struct InterruptContext {
     display: disp::Display,
     i2c1_state: I2C1State,
     i2c_driver: i2c::I2CDriver,
+    i2c0: lpc11u6x_pac::I2C0,
+    iocon: lpc11u6x_pac::IOCON,
     settings: settings::Settings,
     gpio: lpc11u6x_pac::GPIO_PORT,
-    adc: adc::Adc,
+    sampler: telemetry::Sampler,
+    sweep: sweep::Sweep,
+    energy: energy::EnergyMeter,
+    history: &'static mut history::History,
+    uptime_ms: u32,
+    next_preset_combo: pins::Button,
+    previous_preset_combo: pins::Button,
+    /// Preset button combo pressed while I2C0 was taken
+    preset_step: Option<PresetStep>,
+}
+
+/// Direction of the ENTER+UP and ENTER+DOWN button combos
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PresetStep {
+    Next,
+    Previous,
+}
+
+fn display_row(
+    mode: u8,
+    snapshot: &telemetry::Snapshot,
+    energy: &energy::EnergyMeter,
+) -> [Character; 4] {
+    let l1 = snapshot.output(vrm::Loop::L1);
+    let l2 = snapshot.output(vrm::Loop::L2);
+    match mode {
+        0 => disp::voltage_into_row(l1.voltage_mv),
+        1 => disp::current_into_row(l1.current_a),
+        2 => disp::temp_into_row(l1.temp_c),
+        3 => disp::voltage_into_row(l2.voltage_mv),
+        4 => disp::current_into_row(l2.current_a),
+        5 => disp::temp_into_row(l2.temp_c),
+        6 => disp::power_into_row(energy.average_power_w(vrm::Loop::L1)),
+        7 => disp::energy_into_row(energy.energy_wh(vrm::Loop::L1)),
+        8 => disp::power_into_row(energy.average_power_w(vrm::Loop::L2)),
+        9 => disp::energy_into_row(energy.energy_wh(vrm::Loop::L2)),
+        _ => [Character::F, Character::F, Character::F, Character::F],
+    }
 }
 
 fn on_interrupt(_cs: &CriticalSection, source: InterruptSource) {
@@ -254,7 +325,30 @@ fn on_interrupt(_cs: &CriticalSection, source: InterruptSource) {
             }
         }
         InterruptSource::Timer16B0 => {
-            ctx.display.increment_digit(&ctx.gpio, &ctx.i2c_driver);
+            ctx.uptime_ms = ctx.uptime_ms.wrapping_add(1);
+            // ENTER+UP and ENTER+DOWN step through the presets, the edges of UP and DOWN bounce
+            let enter = pins::is_enter_pressed(&ctx.gpio);
+            let next = enter && pins::is_up_pressed(&ctx.gpio);
+            let previous = enter && pins::is_down_pressed(&ctx.gpio);
+            if ctx.next_preset_combo.poll(next) {
+                ctx.preset_step = Some(PresetStep::Next);
+            }
+            if ctx.previous_preset_combo.poll(previous) {
+                ctx.preset_step = Some(PresetStep::Previous);
+            }
+            // I2C0 is taken while a proxied read is in progress, a preset step waits for it
+            if !ctx.i2c1_state.is_proxy_active() {
+                match ctx.preset_step.take() {
+                    Some(PresetStep::Next) => {
+                        ctx.settings.select_next_preset(&ctx.i2c_driver, &ctx.gpio)
+                    }
+                    Some(PresetStep::Previous) => ctx
+                        .settings
+                        .select_previous_preset(&ctx.i2c_driver, &ctx.gpio),
+                    None => {}
+                }
+                ctx.display.increment_digit(&ctx.gpio, &ctx.i2c_driver);
+            }
         }
         InterruptSource::Timer16B1 => {
             unsafe {
@@ -264,93 +358,69 @@ fn on_interrupt(_cs: &CriticalSection, source: InterruptSource) {
                     .write(|w| w.bits(4));
             }
 
-            match ctx.settings.get_disp_top_mode() {
-                0 => {
-                    ctx.display.set_row_top(disp::voltage_into_row(
-                        vrm::read_voltage_l1(&ctx.i2c_driver).unwrap_or(0.0),
-                    ));
-                }
-                1 => {
-                    ctx.display.set_row_top(disp::current_into_row(
-                        vrm::read_current_l1(&ctx.i2c_driver).unwrap_or(0),
-                    ));
-                }
-                2 => {
-                    ctx.display.set_row_top(disp::temp_into_row(
-                        vrm::read_temp_l1(&ctx.i2c_driver).unwrap_or(0),
-                    ));
-                }
-                3 => {
-                    ctx.display.set_row_top(disp::voltage_into_row(
-                        vrm::read_voltage_l2(&ctx.i2c_driver).unwrap_or(0.0),
-                    ));
-                }
-                4 => {
-                    ctx.display.set_row_top(disp::current_into_row(
-                        vrm::read_current_l2(&ctx.i2c_driver).unwrap_or(0),
-                    ));
-                }
-                5 => {
-                    ctx.display.set_row_top(disp::temp_into_row(
-                        vrm::read_temp_l2(&ctx.i2c_driver).unwrap_or(0),
-                    ));
-                }
-                _ => {
-                    ctx.display.set_row_top([
-                        Character::F,
-                        Character::F,
-                        Character::F,
-                        Character::F,
-                    ]);
+            for _ in 0..journal::DUMP_ENTRIES_PER_TICK {
+                match ctx.i2c1_state.journal_mut().next_dump_entry() {
+                    Some(entry) => ctx.dbg.debug_journal_entry(&entry),
+                    None => break,
                 }
             }
-            match ctx.settings.get_disp_bottom_mode() {
-                0 => {
-                    ctx.display.set_row_bottom(disp::voltage_into_row(
-                        vrm::read_voltage_l1(&ctx.i2c_driver).unwrap_or(0.0),
-                    ));
-                }
-                1 => {
-                    ctx.display.set_row_bottom(disp::current_into_row(
-                        vrm::read_current_l1(&ctx.i2c_driver).unwrap_or(0),
-                    ));
-                }
-                2 => {
-                    ctx.display.set_row_bottom(disp::temp_into_row(
-                        vrm::read_temp_l1(&ctx.i2c_driver).unwrap_or(0),
-                    ));
-                }
-                3 => {
-                    ctx.display.set_row_bottom(disp::voltage_into_row(
-                        vrm::read_voltage_l2(&ctx.i2c_driver).unwrap_or(0.0),
-                    ));
-                }
-                4 => {
-                    ctx.display.set_row_bottom(disp::current_into_row(
-                        vrm::read_current_l2(&ctx.i2c_driver).unwrap_or(0),
-                    ));
-                }
-                5 => {
-                    ctx.display.set_row_bottom(disp::temp_into_row(
-                        vrm::read_temp_l2(&ctx.i2c_driver).unwrap_or(0),
-                    ));
-                }
-                _ => {
-                    ctx.display.set_row_bottom([
-                        Character::F,
-                        Character::F,
-                        Character::F,
-                        Character::F,
-                    ]);
+
+            if ctx.i2c1_state.take_export_request() {
+                ctx.dbg.debug_settings_export(&ctx.settings.export());
+            }
+
+            ctx.i2c1_state.service_proxy(&ctx.i2c0, ctx.uptime_ms);
+            if ctx.i2c1_state.is_proxy_active() {
+                return;
+            }
+
+            let snapshot = *ctx
+                .sampler
+                .sample(&ctx.i2c_driver, &ctx.gpio, ctx.uptime_ms);
+            if ctx.sampler.is_bus_stuck() {
+                if !bus_recovery::recover_i2c0(&ctx.iocon, &ctx.gpio, &ctx.i2c0) {
+                    ctx.dbg.debug("I2C0 SDA still held low after bus recovery");
                 }
+                ctx.sampler.bus_recovered();
+            }
+            ctx.i2c1_state
+                .record_sample(&snapshot, ctx.sampler.bus_recoveries());
+            ctx.energy.add_sample(
+                snapshot.timestamp_ms,
+                snapshot.output(vrm::Loop::L1).power_mw(),
+                snapshot.output(vrm::Loop::L2).power_mw(),
+            );
+            ctx.sweep.tick(&ctx.i2c_driver, &snapshot);
+
+            // one history sample per second, the display also stays readable at that rate
+            if ctx.sampler.sample_count() % telemetry::SAMPLE_RATE_HZ == 0 {
+                ctx.history.record(&snapshot);
+                ctx.display.set_row_top(display_row(
+                    ctx.settings.get_disp_top_mode(),
+                    &snapshot,
+                    &ctx.energy,
+                ));
+                ctx.display.set_row_bottom(display_row(
+                    ctx.settings.get_disp_bottom_mode(),
+                    &snapshot,
+                    &ctx.energy,
+                ));
             }
         }
+        InterruptSource::I2C0 => ctx
+            .i2c1_state
+            .handle_proxy_interrupt(&ctx.i2c0, ctx.uptime_ms),
         InterruptSource::I2C1 => ctx.i2c1_state.handle_state(
             &mut ctx.settings,
             &ctx.i2c_driver,
+            &ctx.i2c0,
             &mut ctx.display,
-            &ctx.adc,
+            ctx.sampler.snapshot(),
             &ctx.gpio,
+            &mut ctx.sweep,
+            &mut ctx.energy,
+            ctx.history,
+            ctx.uptime_ms,
         ),
         InterruptSource::Other(irqn) => {
             ctx.dbg.debug("Unhandled Interrupt: ");
@@ -359,6 +429,18 @@ fn on_interrupt(_cs: &CriticalSection, source: InterruptSource) {
     }
 }
 
+fn on_wakeup(_cs: &CriticalSection) -> Option<settings::PendingWrite> {
+    //! Runs after every interrupt, for work that should not hold up the interrupt handlers.
+    //! Returns settings that are to be written once the critical section is left.
+    let ctx = unsafe {
+        INTERRUPT_CTX
+            .get_mut()
+            .expect("Interrupt Context has been set")
+    };
+    ctx.i2c1_state.service(&mut ctx.settings);
+    ctx.settings.service(ctx.uptime_ms)
+}
+
 #[interrupt]
 fn GINT0() {
     cortex_m::interrupt::free(|cs| {
@@ -435,6 +517,13 @@ fn CT16B1() {
     });
 }
 
+#[interrupt]
+fn I2C0() {
+    cortex_m::interrupt::free(|cs| {
+        on_interrupt(cs, InterruptSource::I2C0);
+    });
+}
+
 #[interrupt]
 fn I2C1() {
     cortex_m::interrupt::free(|cs| {
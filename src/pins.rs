@@ -1 +1,51 @@
+pub fn is_enter_pressed(gpio: &lpc11u6x_pac::GPIO_PORT) -> bool {
+    // ENTER is connected to PIO1_28 and pulls the pin low while pressed
+    gpio.pin[1].read().bits() & (1 << 28) == 0
+}
 
+pub fn is_return_pressed(gpio: &lpc11u6x_pac::GPIO_PORT) -> bool {
+    // RETURN is connected to PIO1_30 and pulls the pin low while pressed
+    gpio.pin[1].read().bits() & (1 << 30) == 0
+}
+
+pub fn is_up_pressed(gpio: &lpc11u6x_pac::GPIO_PORT) -> bool {
+    // UP is connected to PIO1_21 and pulls the pin low while pressed
+    gpio.pin[1].read().bits() & (1 << 21) == 0
+}
+
+pub fn is_down_pressed(gpio: &lpc11u6x_pac::GPIO_PORT) -> bool {
+    // DOWN is connected to PIO0_8 and pulls the pin low while pressed
+    gpio.pin[0].read().bits() & (1 << 8) == 0
+}
+
+/// Time a button has to be held down before the press counts, longer than its contacts bounce
+const DEBOUNCE_MS: u16 = 20;
+
+/// Debounces a button, or a combination of buttons, polled once per millisecond
+pub struct Button {
+    pressed: bool,
+    stable_ms: u16,
+}
+
+impl Button {
+    pub const fn new() -> Self {
+        Button {
+            pressed: false,
+            stable_ms: 0,
+        }
+    }
+
+    pub fn poll(&mut self, pressed: bool) -> bool {
+        //! True once per press, after the button was held down for `DEBOUNCE_MS`
+        if pressed != self.pressed {
+            self.pressed = pressed;
+            self.stable_ms = 0;
+            return false;
+        }
+        if self.stable_ms >= DEBOUNCE_MS {
+            return false;
+        }
+        self.stable_ms += 1;
+        pressed && self.stable_ms == DEBOUNCE_MS
+    }
+}
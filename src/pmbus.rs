@@ -0,0 +1,161 @@
+use crate::i2c::{I2CDriver, I2cParam, I2cResult};
+use crate::rom::ErrorCode;
+pub use epower_protocol::pmbus::*;
+
+/// PMBus address of the IR3595.
+///
+/// The controller answers PMBus commands and its raw register map (`iic::IR3595_ADDR`) on
+/// separate addresses, this has to match the PMBus address configured in the controller.
+pub const IR3595_PMBUS_ADDR: u8 = 0x40;
+
+/// Longest block a PMBus block read may return
+pub const MAX_BLOCK_LEN: usize = 32;
+
+fn read(i2c: &I2CDriver, cmd: u8, data: &mut [u8]) -> bool {
+    let mut send_buffer = [IR3595_PMBUS_ADDR << 1, cmd];
+    data[0] = (IR3595_PMBUS_ADDR << 1) | 1;
+    let mut param = I2cParam {
+        num_bytes_rec: data.len() as u32,
+        num_bytes_send: 2,
+        buffer_ptr_rec: data.as_mut_ptr(),
+        buffer_ptr_send: send_buffer.as_mut_ptr(),
+        func_pt: None,
+        stop_flag: 1,
+        dummy: [0, 0, 0],
+    };
+    let result = &mut I2cResult {
+        n_bytes_recd: 0,
+        n_bytes_sent: 0,
+    };
+    i2c.master_tx_rx_poll(&mut param, result) == ErrorCode::LPC_OK
+}
+
+fn write(i2c: &I2CDriver, data: &[u8]) -> bool {
+    //! `data` is the command code followed by up to two data bytes
+    let mut send_buffer = [IR3595_PMBUS_ADDR << 1, 0, 0, 0];
+    send_buffer[1..=data.len()].copy_from_slice(data);
+    let mut param = I2cParam {
+        num_bytes_rec: 0,
+        num_bytes_send: data.len() as u32 + 1,
+        buffer_ptr_rec: core::ptr::null_mut::<u8>(),
+        buffer_ptr_send: send_buffer.as_mut_ptr(),
+        func_pt: None,
+        stop_flag: 1,
+        dummy: [0, 0, 0],
+    };
+    let result = &mut I2cResult {
+        n_bytes_recd: 0,
+        n_bytes_sent: 0,
+    };
+    i2c.master_transmit_poll(&mut param, result) == ErrorCode::LPC_OK
+}
+
+pub fn send_byte(i2c: &I2CDriver, cmd: u8) -> bool {
+    write(i2c, &[cmd])
+}
+
+pub fn write_byte(i2c: &I2CDriver, cmd: u8, value: u8) -> bool {
+    write(i2c, &[cmd, value])
+}
+
+pub fn write_word(i2c: &I2CDriver, cmd: u8, value: u16) -> bool {
+    let [lower, higher] = value.to_le_bytes();
+    write(i2c, &[cmd, lower, higher])
+}
+
+pub fn read_byte(i2c: &I2CDriver, cmd: u8) -> Option<u8> {
+    let mut data = [0];
+    read(i2c, cmd, &mut data).then_some(data[0])
+}
+
+pub fn read_word(i2c: &I2CDriver, cmd: u8) -> Option<u16> {
+    //! PMBus words are sent LSB first
+    let mut data = [0; 2];
+    read(i2c, cmd, &mut data).then_some(u16::from_le_bytes(data))
+}
+
+pub fn read_block(i2c: &I2CDriver, cmd: u8, block: &mut [u8; MAX_BLOCK_LEN]) -> Option<usize> {
+    //! Returns the number of valid bytes in `block`
+    let mut data = [0; MAX_BLOCK_LEN + 1];
+    if !read(i2c, cmd, &mut data) {
+        return None;
+    }
+    let len = (data[0] as usize).min(MAX_BLOCK_LEN);
+    block[..len].copy_from_slice(&data[1..=len]);
+    Some(len)
+}
+
+pub fn set_page(i2c: &I2CDriver, page: u8) -> bool {
+    write_byte(i2c, commands::PAGE, page)
+}
+
+pub fn clear_faults(i2c: &I2CDriver) -> bool {
+    send_byte(i2c, commands::CLEAR_FAULTS)
+}
+
+pub fn read_vout_mode(i2c: &I2CDriver, page: u8) -> Option<VoutMode> {
+    set_page(i2c, page).then_some(())?;
+    Some(read_byte(i2c, commands::VOUT_MODE)?.into())
+}
+
+pub fn read_vout(i2c: &I2CDriver, page: u8) -> Option<f32> {
+    //! Output voltage of `page` in V
+    let mode = read_vout_mode(i2c, page)?;
+    decode_vout(read_word(i2c, commands::READ_VOUT)?, mode)
+}
+
+pub fn set_vout_command(i2c: &I2CDriver, page: u8, voltage: f32) -> bool {
+    //! Only supported while the page is in linear VOUT mode
+    match read_vout_mode(i2c, page) {
+        Some(VoutMode::Linear(exponent)) => write_word(
+            i2c,
+            commands::VOUT_COMMAND,
+            f32_to_linear16(voltage, exponent),
+        ),
+        _ => false,
+    }
+}
+
+fn read_linear11(i2c: &I2CDriver, page: u8, cmd: u8) -> Option<f32> {
+    set_page(i2c, page).then_some(())?;
+    Some(linear11_to_f32(read_word(i2c, cmd)?))
+}
+
+pub fn read_vin(i2c: &I2CDriver) -> Option<f32> {
+    //! Input voltage in V
+    read_linear11(i2c, 0, commands::READ_VIN)
+}
+
+pub fn read_iin(i2c: &I2CDriver) -> Option<f32> {
+    //! Input current in A
+    read_linear11(i2c, 0, commands::READ_IIN)
+}
+
+pub fn read_iout(i2c: &I2CDriver, page: u8) -> Option<f32> {
+    //! Output current of `page` in A
+    read_linear11(i2c, page, commands::READ_IOUT)
+}
+
+pub fn read_pout(i2c: &I2CDriver, page: u8) -> Option<f32> {
+    //! Output power of `page` in W
+    read_linear11(i2c, page, commands::READ_POUT)
+}
+
+pub fn read_temperature_1(i2c: &I2CDriver, page: u8) -> Option<f32> {
+    //! Temperature of `page` in °C
+    read_linear11(i2c, page, commands::READ_TEMPERATURE_1)
+}
+
+pub fn read_status_byte(i2c: &I2CDriver, page: u8) -> Option<u8> {
+    set_page(i2c, page).then_some(())?;
+    read_byte(i2c, commands::STATUS_BYTE)
+}
+
+pub fn read_status_word(i2c: &I2CDriver, page: u8) -> Option<u16> {
+    set_page(i2c, page).then_some(())?;
+    read_word(i2c, commands::STATUS_WORD)
+}
+
+pub fn read_mfr_id(i2c: &I2CDriver, block: &mut [u8; MAX_BLOCK_LEN]) -> Option<usize> {
+    read_block(i2c, commands::MFR_ID, block)
+}
@@ -0,0 +1,473 @@
+use crate::iic::IR3595_ADDR_SHIFTED;
+use crate::journal::{Journal, WriteResult};
+use epower_protocol::evbot::{ReadEvent, RECIEVE_BUFFER_SIZE};
+
+/// Polls of SI before a step on I2C0 is given up, a byte at 1MHz takes well below that
+const SI_TIMEOUT: u32 = 10_000;
+/// An EVBot read stalling, or a write stuck on I2C0, for longer than this is aborted, so that
+/// I2C0 becomes usable again
+const STALLED_MS: u32 = 50;
+/// Writes waiting for I2C0, one more arriving while all are taken is dropped
+const WRITE_QUEUE_LENGTH: usize = 4;
+
+mod master_states {
+    /// A START condition has been transmitted.
+    pub const START: u32 = 0x08;
+    /// A Repeated START condition has been transmitted.
+    pub const REPEATED_START: u32 = 0x10;
+    /// SLA+W has been transmitted; ACK has been received.
+    pub const ADDRESS_WRITE_ACKED: u32 = 0x18;
+    /// Data byte in DAT has been transmitted; ACK has been received.
+    pub const DATA_WRITE_ACKED: u32 = 0x28;
+    /// SLA+R has been transmitted; ACK has been received.
+    pub const ADDRESS_READ_ACKED: u32 = 0x40;
+    /// Data byte has been received; ACK has been returned.
+    pub const DATA_READ_ACKED: u32 = 0x50;
+    /// Data byte has been received; NOT ACK has been returned.
+    pub const DATA_READ_NOT_ACKED: u32 = 0x58;
+}
+
+fn wait(i2c0: &lpc11u6x_pac::I2C0) -> Option<u32> {
+    for _ in 0..SI_TIMEOUT {
+        if i2c0.conset.read().si().bit_is_set() {
+            return Some(i2c0.stat.read().bits());
+        }
+    }
+    None
+}
+
+fn stop(i2c0: &lpc11u6x_pac::I2C0) {
+    i2c0.conset.write(|w| w.sto().set_bit());
+    i2c0.conclr.write(|w| w.sic().set_bit());
+}
+
+fn set_interrupt(enabled: bool) {
+    //! The I2C0 interrupt is only enabled while a step of a proxied transfer is on the bus, SI
+    //! stays set in between and the ROM driver polls it on its own
+    if enabled {
+        unsafe { cortex_m::peripheral::NVIC::unmask(lpc11u6x_pac::Interrupt::I2C0) };
+    } else {
+        cortex_m::peripheral::NVIC::mask(lpc11u6x_pac::Interrupt::I2C0);
+        cortex_m::peripheral::NVIC::unpend(lpc11u6x_pac::Interrupt::I2C0);
+    }
+}
+
+/// Steps of a proxied transfer on I2C0, each one ends with an I2C0 interrupt
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Step {
+    Idle,
+    /// START of the first queued write
+    StartForward,
+    AddressForward,
+    ForwardData,
+    /// START of the write carrying the command byte
+    StartWrite,
+    AddressWrite,
+    Command,
+    /// START or repeated START of the read
+    StartRead,
+    AddressRead,
+    Data,
+    /// A byte was passed on to the EVBot master, SCL is held low until the next one is wanted
+    Holding,
+    /// Reading the byte that is dropped so that I2C0 can be stopped
+    Draining,
+}
+
+/// A write from the EVBot master waiting for I2C0
+#[derive(Debug, Copy, Clone)]
+struct QueuedWrite {
+    data: [u8; RECIEVE_BUFFER_SIZE],
+    len: usize,
+    /// Uptime when the EVBot master wrote it, for the journal
+    timestamp_ms: u32,
+    /// Held back command bytes forwarded on their own are not journaled
+    journaled: bool,
+}
+
+impl QueuedWrite {
+    const EMPTY: QueuedWrite = QueuedWrite {
+        data: [0; RECIEVE_BUFFER_SIZE],
+        len: 0,
+        timestamp_ms: 0,
+        journaled: false,
+    };
+
+    fn data(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+/// Mirrors transactions from the EVBot connector onto I2C0, byte by byte, on register level.
+///
+/// A command byte written on its own is held back. If a read follows it is sent as the write
+/// part of a combined transaction with repeated START, otherwise it is forwarded on its own.
+///
+/// Everything runs from the I2C0 interrupt. Reads keep the EVBot master stretched until each
+/// byte is in. Writes are queued, since the EVBot master is done with them at its STOP, and
+/// are journaled once the IR3595 took them or not.
+pub struct Proxy {
+    pending_cmd: Option<u8>,
+    /// Read-only commands are only sent as part of a combined read, never on their own
+    pending_writable: bool,
+    read_started_ms: Option<u32>,
+    step: Step,
+    /// Command the current read started at, if any
+    read_cmd: Option<u8>,
+    /// Only one byte is read and I2C0 is stopped right away, for reads with PEC
+    single: bool,
+    /// The EVBot read ended while a step was still on I2C0
+    abandoned: bool,
+    /// A read waits for the previous one to be drained or for the queued writes
+    queued: bool,
+    writes: [QueuedWrite; WRITE_QUEUE_LENGTH],
+    first_write: usize,
+    queued_writes: usize,
+    /// Next byte of the first queued write to go on I2C0
+    write_index: usize,
+    write_started_ms: u32,
+}
+
+impl Proxy {
+    pub fn new() -> Self {
+        Proxy {
+            pending_cmd: None,
+            pending_writable: false,
+            read_started_ms: None,
+            step: Step::Idle,
+            read_cmd: None,
+            single: false,
+            abandoned: false,
+            queued: false,
+            writes: [QueuedWrite::EMPTY; WRITE_QUEUE_LENGTH],
+            first_write: 0,
+            queued_writes: 0,
+            write_index: 0,
+            write_started_ms: 0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        //! While a read is open or drained, or writes are queued, no one else may use I2C0
+        self.read_started_ms.is_some() || self.step != Step::Idle
+    }
+
+    pub fn set_pending_cmd(&mut self, cmd: u8, writable: bool) {
+        self.pending_cmd = Some(cmd);
+        self.pending_writable = writable;
+    }
+
+    pub fn pending_cmd(&self) -> Option<u8> {
+        self.pending_cmd
+    }
+
+    pub fn discard_pending_cmd(&mut self) {
+        self.pending_cmd = None;
+    }
+
+    pub fn read_cmd(&self) -> Option<u8> {
+        self.read_cmd
+    }
+
+    fn flush(&mut self, i2c0: &lpc11u6x_pac::I2C0, now_ms: u32, journal: &mut Journal) {
+        //! Forwards a command byte that turned out not to be followed by a read
+        if let Some(cmd) = self.pending_cmd.take().filter(|_| self.pending_writable) {
+            self.queue_write(i2c0, &[cmd], now_ms, false, journal);
+        }
+    }
+
+    pub fn write(
+        &mut self,
+        i2c0: &lpc11u6x_pac::I2C0,
+        data: &[u8],
+        now_ms: u32,
+        journal: &mut Journal,
+    ) {
+        //! Queues the write, the result is journaled once it is done on I2C0
+        self.flush(i2c0, now_ms, journal);
+        self.queue_write(i2c0, data, now_ms, true, journal);
+    }
+
+    fn queue_write(
+        &mut self,
+        i2c0: &lpc11u6x_pac::I2C0,
+        data: &[u8],
+        now_ms: u32,
+        journaled: bool,
+        journal: &mut Journal,
+    ) {
+        if self.queued_writes == WRITE_QUEUE_LENGTH {
+            if journaled {
+                journal.record(now_ms, data, WriteResult::NotAcknowledged);
+            }
+            return;
+        }
+        let write = &mut self.writes[(self.first_write + self.queued_writes) % WRITE_QUEUE_LENGTH];
+        write.len = data.len().min(RECIEVE_BUFFER_SIZE);
+        write.data[..write.len].copy_from_slice(&data[..write.len]);
+        write.timestamp_ms = now_ms;
+        write.journaled = journaled;
+        self.queued_writes += 1;
+        if self.step == Step::Idle {
+            self.open_write(i2c0, now_ms);
+        }
+    }
+
+    fn open_write(&mut self, i2c0: &lpc11u6x_pac::I2C0, now_ms: u32) {
+        self.write_index = 0;
+        self.write_started_ms = now_ms;
+        self.step = Step::StartForward;
+        i2c0.conset.write(|w| w.i2en().set_bit().sta().set_bit());
+        i2c0.conclr.write(|w| w.sic().set_bit());
+        set_interrupt(true);
+    }
+
+    fn finish_write(
+        &mut self,
+        i2c0: &lpc11u6x_pac::I2C0,
+        now_ms: u32,
+        journal: &mut Journal,
+        result: WriteResult,
+    ) {
+        let write = &self.writes[self.first_write];
+        if write.journaled {
+            journal.record(write.timestamp_ms, write.data(), result);
+        }
+        self.first_write = (self.first_write + 1) % WRITE_QUEUE_LENGTH;
+        self.queued_writes -= 1;
+        self.abort(i2c0, now_ms);
+    }
+
+    fn open_read(&mut self, i2c0: &lpc11u6x_pac::I2C0) {
+        self.abandoned = false;
+        self.queued = false;
+        self.step = if self.read_cmd.is_some() {
+            Step::StartWrite
+        } else {
+            Step::StartRead
+        };
+        i2c0.conset.write(|w| w.i2en().set_bit().sta().set_bit());
+        i2c0.conclr.write(|w| w.sic().set_bit());
+        set_interrupt(true);
+    }
+
+    pub fn start_read(&mut self, i2c0: &lpc11u6x_pac::I2C0, now_ms: u32, single: bool) {
+        //! The first byte is returned by `handle_interrupt`. Every byte is ACKed on I2C0 since it
+        //! is unknown whether the EVBot master wants more, unless `single` is set.
+        self.finish_read(i2c0);
+        self.read_cmd = self.pending_cmd.take();
+        self.read_started_ms = Some(now_ms);
+        self.single = single;
+        if self.step == Step::Idle {
+            self.open_read(i2c0);
+        } else {
+            self.queued = true;
+        }
+    }
+
+    pub fn read_next(&mut self, i2c0: &lpc11u6x_pac::I2C0) -> bool {
+        //! Requests the next byte, false if there is no open read to continue
+        if self.step != Step::Holding || self.read_started_ms.is_none() {
+            return false;
+        }
+        self.step = Step::Data;
+        i2c0.conset.write(|w| w.aa().set_bit());
+        i2c0.conclr.write(|w| w.sic().set_bit());
+        set_interrupt(true);
+        true
+    }
+
+    fn drain(&mut self, i2c0: &lpc11u6x_pac::I2C0) {
+        //! I2C0 may only be stopped after a NACKed byte, so one extra byte is read and dropped
+        self.step = Step::Draining;
+        i2c0.conclr.write(|w| w.aac().set_bit().sic().set_bit());
+        set_interrupt(true);
+    }
+
+    pub fn finish_read(&mut self, i2c0: &lpc11u6x_pac::I2C0) {
+        self.read_started_ms = None;
+        self.queued = false;
+        match self.step {
+            Step::Idle | Step::Draining => {}
+            _ if self.is_forwarding() => {}
+            Step::Holding => self.drain(i2c0),
+            // the step on the bus is finished by `handle_interrupt`
+            _ => self.abandoned = true,
+        }
+    }
+
+    fn abort(&mut self, i2c0: &lpc11u6x_pac::I2C0, now_ms: u32) {
+        //! Stops I2C0 and starts whatever waits for it, queued writes go before a read
+        stop(i2c0);
+        self.step = Step::Idle;
+        if self.queued_writes > 0 {
+            self.open_write(i2c0, now_ms);
+        } else if self.queued {
+            self.open_read(i2c0);
+        } else {
+            set_interrupt(false);
+        }
+    }
+
+    pub fn handle_interrupt(
+        &mut self,
+        i2c0: &lpc11u6x_pac::I2C0,
+        now_ms: u32,
+        journal: &mut Journal,
+    ) -> Option<ReadEvent> {
+        //! Moves the transfer on by one step, returns what the EVBot master gets once a byte of a
+        //! read is in
+        if !self.is_on_bus() || i2c0.conset.read().si().bit_is_clear() {
+            return None;
+        }
+        let stat = i2c0.stat.read().bits();
+        match (self.step, stat) {
+            (Step::StartForward, master_states::START | master_states::REPEATED_START) => {
+                unsafe { i2c0.dat.write(|w| w.bits(IR3595_ADDR_SHIFTED as u32)) };
+                i2c0.conclr.write(|w| w.stac().set_bit().sic().set_bit());
+                self.step = Step::AddressForward;
+            }
+            (Step::AddressForward, master_states::ADDRESS_WRITE_ACKED)
+            | (Step::ForwardData, master_states::DATA_WRITE_ACKED) => {
+                let write = &self.writes[self.first_write];
+                match write.data().get(self.write_index) {
+                    Some(&byte) => {
+                        unsafe { i2c0.dat.write(|w| w.bits(byte as u32)) };
+                        i2c0.conclr.write(|w| w.sic().set_bit());
+                        self.write_index += 1;
+                        self.step = Step::ForwardData;
+                    }
+                    None => self.finish_write(i2c0, now_ms, journal, WriteResult::Forwarded),
+                }
+            }
+            // NACKed address or data, lost arbitration or a bus error
+            _ if self.is_forwarding() => {
+                self.finish_write(i2c0, now_ms, journal, WriteResult::NotAcknowledged)
+            }
+            (Step::StartWrite, master_states::START) => {
+                unsafe { i2c0.dat.write(|w| w.bits(IR3595_ADDR_SHIFTED as u32)) };
+                i2c0.conclr.write(|w| w.stac().set_bit().sic().set_bit());
+                self.step = Step::AddressWrite;
+            }
+            (Step::AddressWrite, master_states::ADDRESS_WRITE_ACKED) => {
+                let cmd = self.read_cmd.unwrap_or_default();
+                unsafe { i2c0.dat.write(|w| w.bits(cmd as u32)) };
+                i2c0.conclr.write(|w| w.sic().set_bit());
+                self.step = Step::Command;
+            }
+            (Step::Command, master_states::DATA_WRITE_ACKED) => {
+                i2c0.conset.write(|w| w.sta().set_bit());
+                i2c0.conclr.write(|w| w.sic().set_bit());
+                self.step = Step::StartRead;
+            }
+            (Step::StartRead, master_states::START | master_states::REPEATED_START) => {
+                let addr_byte = IR3595_ADDR_SHIFTED | 1;
+                unsafe { i2c0.dat.write(|w| w.bits(addr_byte as u32)) };
+                i2c0.conclr.write(|w| w.stac().set_bit().sic().set_bit());
+                self.step = Step::AddressRead;
+            }
+            (Step::AddressRead, master_states::ADDRESS_READ_ACKED) => {
+                if self.single {
+                    i2c0.conclr.write(|w| w.aac().set_bit().sic().set_bit());
+                } else {
+                    i2c0.conset.write(|w| w.aa().set_bit());
+                    i2c0.conclr.write(|w| w.sic().set_bit());
+                }
+                self.step = Step::Data;
+            }
+            (Step::Data, master_states::DATA_READ_ACKED) => {
+                let value = i2c0.dat.read().bits() as u8;
+                if self.abandoned {
+                    self.drain(i2c0);
+                    return None;
+                }
+                set_interrupt(false);
+                self.step = Step::Holding;
+                return Some(ReadEvent::Byte(value));
+            }
+            (Step::Data, master_states::DATA_READ_NOT_ACKED) => {
+                let value = i2c0.dat.read().bits() as u8;
+                let abandoned = self.abandoned;
+                self.abort(i2c0, now_ms);
+                return (!abandoned).then_some(ReadEvent::Byte(value));
+            }
+            (Step::Draining, master_states::DATA_READ_NOT_ACKED) => self.abort(i2c0, now_ms),
+            // NACKed address or command, lost arbitration or a bus error
+            _ => {
+                let abandoned = self.abandoned || self.step == Step::Draining;
+                self.abort(i2c0, now_ms);
+                if !abandoned {
+                    self.read_started_ms = None;
+                    return Some(ReadEvent::Failed);
+                }
+            }
+        }
+        None
+    }
+
+    fn is_on_bus(&self) -> bool {
+        //! A step was started and its interrupt is still to come
+        !matches!(self.step, Step::Idle | Step::Holding)
+    }
+
+    fn is_forwarding(&self) -> bool {
+        matches!(
+            self.step,
+            Step::StartForward | Step::AddressForward | Step::ForwardData
+        )
+    }
+
+    pub fn settle(&mut self, i2c0: &lpc11u6x_pac::I2C0, now_ms: u32, journal: &mut Journal) {
+        //! Polls queued writes and the drain of a finished read to their end, before the Epower
+        //! itself uses I2C0
+        while self.is_on_bus() {
+            if wait(i2c0).is_none() {
+                if self.is_forwarding() {
+                    self.finish_write(i2c0, now_ms, journal, WriteResult::NotAcknowledged);
+                } else {
+                    self.abort(i2c0, now_ms);
+                }
+                continue;
+            }
+            self.handle_interrupt(i2c0, now_ms, journal);
+        }
+    }
+
+    pub fn service(
+        &mut self,
+        i2c0: &lpc11u6x_pac::I2C0,
+        now_ms: u32,
+        journal: &mut Journal,
+    ) -> Option<ReadEvent> {
+        //! Aborts stalled transfers and forwards held back command bytes. An EVBot master still
+        //! waiting for a read stuck on I2C0 is answered with a failed read.
+        if self.is_forwarding() && now_ms.wrapping_sub(self.write_started_ms) > STALLED_MS {
+            self.finish_write(i2c0, now_ms, journal, WriteResult::NotAcknowledged);
+        }
+        if let Some(started_ms) = self.read_started_ms {
+            if now_ms.wrapping_sub(started_ms) <= STALLED_MS {
+                return None;
+            }
+            // still behind the queued writes, which go on
+            if self.queued {
+                self.queued = false;
+                self.read_started_ms = None;
+                return Some(ReadEvent::Failed);
+            }
+            if self.is_on_bus() {
+                self.read_started_ms = None;
+                self.abort(i2c0, now_ms);
+                return Some(ReadEvent::Failed);
+            }
+            self.finish_read(i2c0);
+        }
+        self.flush(i2c0, now_ms, journal);
+        None
+    }
+}
+
+impl Default for Proxy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -58,6 +58,20 @@ pub fn get_uid() -> u32 {
     result[1]
 }
 
+pub fn reinvoke_isp() -> ! {
+    //! Enters the UART ISP bootloader of the ROM, it only returns to the firmware through a reset
+    let mut command = [0u32; 5];
+    let mut result = [0u32; 4];
+
+    command[0] = 57;
+    command[1] = 1;
+    let ptr = IAP_ENTRY as *const ();
+    let iap_entry =
+        unsafe { core::mem::transmute::<*const (), unsafe extern "C" fn(*mut u32, *mut u32)>(ptr) };
+    iap_entry_func(command.as_mut_ptr(), result.as_mut_ptr(), iap_entry);
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
 pub fn black_check() {
     let mut command = [0u32; 5];
     let mut result = [0u32; 4];
@@ -0,0 +1,40 @@
+use super::{IapStatus, IAP_ENTRY};
+use crate::clock::get_sys_clk;
+
+pub fn prepare_sectors(start: u32, end: u32) -> IapStatus {
+    //! Has to precede every erase and write, the preparation is gone afterwards
+    let mut command = [0u32; 5];
+    command[0] = 50;
+    command[1] = start;
+    command[2] = end;
+    iap(&mut command)
+}
+
+pub fn erase_sectors(start: u32, end: u32, sys: &lpc11u6x_pac::SYSCON) -> IapStatus {
+    let mut command = [0u32; 5];
+    command[0] = 52;
+    command[1] = start;
+    command[2] = end;
+    command[3] = get_sys_clk(sys) / 1000;
+    iap(&mut command)
+}
+
+pub fn copy_ram_to_flash(dst: u32, src: &[u8], sys: &lpc11u6x_pac::SYSCON) -> IapStatus {
+    //! `dst` has to be 256 byte aligned, `src` word aligned and 256, 512, 1024 or 4096 bytes long
+    let mut command = [0u32; 5];
+    command[0] = 51;
+    command[1] = dst;
+    command[2] = src.as_ptr() as u32;
+    command[3] = src.len() as u32;
+    command[4] = get_sys_clk(sys) / 1000;
+    iap(&mut command)
+}
+
+fn iap(command: &mut [u32; 5]) -> IapStatus {
+    let mut result = [0u32; 4];
+    let ptr = IAP_ENTRY as *const ();
+    let iap_entry =
+        unsafe { core::mem::transmute::<*const (), unsafe extern "C" fn(*mut u32, *mut u32)>(ptr) };
+    unsafe { iap_entry(command.as_mut_ptr(), result.as_mut_ptr()) };
+    result[0].into()
+}
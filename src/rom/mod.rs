@@ -6,6 +6,7 @@ pub const IAP_ENTRY: usize = 0x1FFF1FF1;
 pub enum CVoid {}
 
 pub mod eeprom;
+pub mod flash;
 pub mod i2c;
 pub mod uart;
 
@@ -1,32 +1,123 @@
 use crate::{
     disp::{self, Orientation},
-    FIRMWARE_VERSION,
+    rom::i2c::I2CDriver,
+    vrm,
 };
+use epower_protocol::settings::{
+    self as layout, invalid_name, is_valid_setting, is_valid_value, migrate, slave_address,
+    SettingError, BOOT_PRESET_OFFSET, CRC, DISPLAY_BOTTOM_MODE_OFFSET, DISPLAY_ORIENTATION_OFFSET,
+    DISPLAY_TOP_MODE_OFFSET, EPOWER_ADDRESS_OFFSET, EXPORT_SIZE, L1_BOOT_VOLTAGE_OFFSET,
+    L1_ENABLED_OFFSET, L2_BOOT_VOLTAGE_OFFSET, L2_ENABLED_OFFSET, MANUAL_COMMIT_OFFSET, PEC_OFFSET,
+    PMBUS_PERSONALITY_OFFSET, PRESETS_OFFSET, PRESET_COUNT, PRESET_L1_ENABLED, PRESET_L1_VID,
+    PRESET_L2_ENABLED, PRESET_L2_VID, PRESET_NAMES_SIZE, PRESET_NAME_SIZE, PRESET_SIZE,
+    PROXY_ACCESS_RULES_RANGE, PROXY_ADDRESS_OFFSET, PROXY_LOCK_OFFSET, SETTINGS_SCHEMA_VERSION,
+    SETTINGS_VERSION_OFFSET,
+};
+
+/// EEPROM addresses of the two copies of the settings, each one 60 bytes of settings followed by
+/// their checksum. They are written in turns, so a power cut while writing one leaves the other.
+const SLOT_ADDRS: [u32; 2] = [0x400, 0x480];
+const SLOT_SIZE: usize = 64;
+/// Number of times the settings were written (2 bytes, LSB first), the copy with the higher
+/// number is the newer one. Older firmware only wrote the first copy and left 0 in here.
+const SEQUENCE_OFFSET: usize = 57;
+/// EEPROM address of the boot counter, right behind the first copy of the settings
+const BOOT_COUNT_ADDR: u32 = 0x440;
+/// EEPROM addresses of the two copies of the preset names, each one the names of all presets
+/// followed by their checksum. The names are not part of the settings, they have no room in there.
+/// Both copies are written one after the other, so a power cut leaves at least one of them intact.
+const PRESET_NAMES_ADDRS: [u32; 2] = [0x500, 0x540];
+/// Changed settings are written once no further change came in for this long, so a burst of
+/// writes from the EVC2 ends up as a single EEPROM write
+const QUIET_PERIOD_MS: u32 = 500;
+/// Minimum time between two EEPROM writes, which limits the wear when a host keeps changing
+/// settings
+const MIN_WRITE_INTERVAL_MS: u32 = 5000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Preset {
+    pub l1_vid: u8,
+    pub l2_vid: u8,
+    pub l1_enabled: bool,
+    pub l2_enabled: bool,
+}
+
+impl Preset {
+    pub fn is_unused(&self) -> bool {
+        //! Presets that were never written are all 0, VID 0 with both loops off
+        self.l1_vid == 0 && self.l2_vid == 0 && !self.l1_enabled && !self.l2_enabled
+    }
+
+    pub fn apply(&self, i2c: &I2CDriver, gpio: &lpc11u6x_pac::GPIO_PORT) {
+        vrm::set_voltage_l1_raw(i2c, self.l1_vid);
+        vrm::set_voltage_l2_raw(i2c, self.l2_vid);
+        if self.l1_enabled {
+            vrm::enable_l1(gpio);
+        } else {
+            vrm::disable_l1(gpio);
+        }
+        if self.l2_enabled {
+            vrm::enable_l2(gpio);
+        } else {
+            vrm::disable_l2(gpio);
+        }
+    }
+}
 
-const DISPLAY_TOP_MODE_OFFSET: usize = 0;
-const DISPLAY_BOTTOM_MODE_OFFSET: usize = 1;
-const L1_BOOT_VOLTAGE_OFFSET: usize = 2;
-const L2_BOOT_VOLTAGE_OFFSET: usize = 3;
-const L1_ENABLED_OFFSET: usize = 4;
-const L2_ENABLED_OFFSET: usize = 5;
-const DISPLAY_ORIENTATION_OFFSET: usize = 6;
-
-const SETTINGS_VERSION_OFFSET: usize = 59;
-
-const VALID_SETTING_OFFSETS: [usize; 7] = [
-    DISPLAY_TOP_MODE_OFFSET,
-    DISPLAY_BOTTOM_MODE_OFFSET,
-    L1_BOOT_VOLTAGE_OFFSET,
-    L2_BOOT_VOLTAGE_OFFSET,
-    L1_ENABLED_OFFSET,
-    L2_ENABLED_OFFSET,
-    DISPLAY_ORIENTATION_OFFSET,
-];
-const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
+/// Settings and preset names on their way to the EEPROM, taken out of `Settings` so that they can
+/// be written with the interrupts enabled
+pub struct PendingWrite {
+    /// EEPROM address of the copy and its content
+    settings: Option<(u32, [u8; SLOT_SIZE])>,
+    preset_names: Option<[u8; PRESET_NAMES_SIZE + 4]>,
+}
+
+impl PendingWrite {
+    pub fn write(mut self, sys: &lpc11u6x_pac::SYSCON) {
+        //! The interrupt handlers must not call the IAP routines meanwhile, they are not
+        //! reentrant. The copies of the preset names are written one after the other.
+        if let Some((addr, buffer)) = &mut self.settings {
+            crate::rom::eeprom::eeprom_write(
+                *addr,
+                buffer.as_mut_ptr() as u32,
+                buffer.len() as u32,
+                sys,
+            );
+        }
+        if let Some(buffer) = &mut self.preset_names {
+            for addr in PRESET_NAMES_ADDRS {
+                crate::rom::eeprom::eeprom_write(
+                    addr,
+                    buffer.as_mut_ptr() as u32,
+                    buffer.len() as u32,
+                    sys,
+                );
+            }
+        }
+    }
+}
 
 pub struct Settings {
     sys: lpc11u6x_pac::SYSCON,
     cache: [u8; 60],
+    active_preset: u8,
+    boot_count: u32,
+    /// The cache holds changes that are kept back by the manual commit
+    uncommitted: bool,
+    /// The settings as they are to be stored, the cache without uncommitted changes
+    committed: [u8; 60],
+    /// `committed` still has to be written to the EEPROM
+    write_pending: bool,
+    /// Uptime at which the main loop first saw the pending write, `None` after every change
+    quiet_since_ms: Option<u32>,
+    last_write_ms: Option<u32>,
+    /// Index into `SLOT_ADDRS` of the copy holding the current settings
+    slot: usize,
+    sequence: u16,
+    /// Names of the presets 1 to `PRESET_COUNT`, they are not held back by the manual commit
+    preset_names: [u8; PRESET_NAMES_SIZE],
+    /// `preset_names` still have to be written to the EEPROM
+    names_pending: bool,
 }
 
 fn default_settings() -> [u8; 64] {
@@ -37,74 +128,214 @@ fn default_settings() -> [u8; 64] {
     settings[L2_BOOT_VOLTAGE_OFFSET] = 0x6C; // 135xmv
     settings[L1_ENABLED_OFFSET] = 1;
     settings[L2_ENABLED_OFFSET] = 0;
-    settings[SETTINGS_VERSION_OFFSET] = FIRMWARE_VERSION;
+    settings[SETTINGS_VERSION_OFFSET] = SETTINGS_SCHEMA_VERSION;
     settings[DISPLAY_ORIENTATION_OFFSET] = Orientation::default().into();
+    settings[BOOT_PRESET_OFFSET] = 0;
+    settings[PMBUS_PERSONALITY_OFFSET] = 0;
+    settings[PEC_OFFSET] = 0;
+    settings[PROXY_LOCK_OFFSET] = 0;
+    settings[PROXY_ACCESS_RULES_RANGE].fill(0);
+    settings[PROXY_ADDRESS_OFFSET] = 0;
+    settings[EPOWER_ADDRESS_OFFSET] = 0;
+    settings[MANUAL_COMMIT_OFFSET] = 0;
     let checksum = CRC.checksum(&settings[0..60]);
     settings[60..64].copy_from_slice(&checksum.to_le_bytes());
     settings
 }
 
-fn read_buffer_from_eeprom(sys: &lpc11u6x_pac::SYSCON) -> ([u8; 60], [u8; 4]) {
-    //! First buffer is the 60 data bytes, the last of which contains the version
-    //!
-    //! Second buffer is the 4 cheksum bytes
-    let mut buffer = [0; 64];
-    crate::rom::eeprom::eeprom_read(0x400, &mut buffer, sys);
+fn replace_invalid_values(mut settings: [u8; 60]) -> [u8; 60] {
+    //! A matching checksum does not make a value valid, e.g. after the range of a setting was
+    //! narrowed. Such values fall back to their default.
+    let defaults = default_settings();
+    for setting_id in (0..60).filter(|&setting_id| is_valid_setting(setting_id)) {
+        if !is_valid_value(setting_id, settings[setting_id]) {
+            settings[setting_id] = defaults[setting_id];
+        }
+    }
+    settings
+}
+
+fn read_slot(slot: usize, sys: &lpc11u6x_pac::SYSCON) -> Option<[u8; 60]> {
+    //! The 60 data bytes of a copy, the last of which contains the version, if the checksum
+    //! matches
+    let mut buffer = [0; SLOT_SIZE];
+    crate::rom::eeprom::eeprom_read(SLOT_ADDRS[slot], &mut buffer, sys);
     let (data_buffer, checksum_buffer) = buffer.split_at(60);
-    (
-        data_buffer.try_into().expect("data_buffer is 60 bytes"),
+    let checksum = u32::from_le_bytes(
         checksum_buffer
             .try_into()
             .expect("checksum_buffer is 4 bytes"),
-    )
+    );
+    if CRC.checksum(data_buffer) != checksum {
+        return None;
+    }
+    Some(data_buffer.try_into().expect("data_buffer is 60 bytes"))
+}
+
+fn read_preset_names(sys: &lpc11u6x_pac::SYSCON) -> [u8; PRESET_NAMES_SIZE] {
+    //! The first intact copy, without one all presets are unnamed
+    PRESET_NAMES_ADDRS
+        .iter()
+        .find_map(|&addr| {
+            let mut buffer = [0; PRESET_NAMES_SIZE + 4];
+            crate::rom::eeprom::eeprom_read(addr, &mut buffer, sys);
+            let (names, checksum) = buffer.split_at(PRESET_NAMES_SIZE);
+            let checksum = u32::from_le_bytes(checksum.try_into().expect("checksum is 4 bytes"));
+            let names: [u8; PRESET_NAMES_SIZE] =
+                names.try_into().expect("names are PRESET_NAMES_SIZE bytes");
+            (CRC.checksum(&names) == checksum && invalid_name(&names).is_none()).then_some(names)
+        })
+        .unwrap_or([0; PRESET_NAMES_SIZE])
+}
+
+fn sequence(settings: &[u8; 60]) -> u16 {
+    u16::from_le_bytes([settings[SEQUENCE_OFFSET], settings[SEQUENCE_OFFSET + 1]])
+}
+
+fn newest_slot(slots: &[Option<[u8; 60]>; 2]) -> Option<usize> {
+    //! The sequence numbers wrap around, the newer copy is at most half the range ahead
+    match slots {
+        [Some(first), Some(second)] => {
+            let ahead = sequence(second).wrapping_sub(sequence(first)) as i16;
+            Some(if ahead > 0 { 1 } else { 0 })
+        }
+        [Some(_), None] => Some(0),
+        [None, Some(_)] => Some(1),
+        [None, None] => None,
+    }
+}
+
+fn increment_boot_count(sys: &lpc11u6x_pac::SYSCON) -> u32 {
+    //! Erased EEPROM reads as 0xFF, a counter that was never written starts at 0
+    let mut buffer = [0; 4];
+    crate::rom::eeprom::eeprom_read(BOOT_COUNT_ADDR, &mut buffer, sys);
+    let boot_count = match u32::from_le_bytes(buffer) {
+        u32::MAX => 1,
+        count => count.wrapping_add(1),
+    };
+    let mut buffer = boot_count.to_le_bytes();
+    crate::rom::eeprom::eeprom_write(BOOT_COUNT_ADDR, buffer.as_mut_ptr() as u32, 4, sys);
+    boot_count
 }
 
 impl Settings {
     pub fn new(sys: lpc11u6x_pac::SYSCON) -> Self {
-        let (data_buffer, checksum_buffer) = read_buffer_from_eeprom(&sys);
-
-        let checksum = CRC.checksum(&data_buffer);
-        if checksum == u32::from_le_bytes(checksum_buffer)
-            && data_buffer[SETTINGS_VERSION_OFFSET] == crate::FIRMWARE_VERSION
-        {
-            Settings {
-                sys,
-                cache: data_buffer,
-            }
-        } else {
-            let settings = default_settings();
-            crate::rom::eeprom::eeprom_write(
-                0x400,
-                settings.as_ptr() as u32,
-                settings.len() as u32,
-                &sys,
-            );
-            let mut cache = [0; 60];
-            cache.copy_from_slice(&settings[0..60]);
-            Settings { sys, cache }
+        let slots = [read_slot(0, &sys), read_slot(1, &sys)];
+        let newest = newest_slot(&slots);
+        let stored = newest.and_then(|slot| slots[slot]);
+        let migrated = stored.and_then(migrate).map(replace_invalid_values);
+        let cache = migrated.unwrap_or_else(|| {
+            default_settings()[0..60]
+                .try_into()
+                .expect("settings are 60 bytes")
+        });
+        let mut settings = Settings {
+            sys,
+            cache,
+            active_preset: 0,
+            boot_count: 0,
+            uncommitted: false,
+            committed: cache,
+            write_pending: false,
+            quiet_since_ms: None,
+            last_write_ms: None,
+            // without a valid copy the first one is written first
+            slot: newest.unwrap_or(1),
+            // the numbering goes on even when the settings could not be used
+            sequence: stored.as_ref().map_or(0, sequence),
+            preset_names: [0; PRESET_NAMES_SIZE],
+            names_pending: false,
+        };
+        settings.preset_names = read_preset_names(&settings.sys);
+        // defaults, migrated and repaired settings are stored right away
+        if migrated.is_none() || migrated != stored {
+            settings.write_cache_to_eeprom();
         }
+        settings.active_preset = settings.boot_preset_number();
+        settings.boot_count = increment_boot_count(&settings.sys);
+        settings
     }
 
     pub fn write_cache_to_eeprom(&mut self) {
-        crate::rom::eeprom::eeprom_write(
-            0x400,
-            self.cache.as_mut_ptr() as u32,
-            self.cache.len() as u32,
-            &self.sys,
-        );
-        let checksum = CRC.checksum(&self.cache);
-        let mut cheksum_bytes = checksum.to_le_bytes();
-        crate::rom::eeprom::eeprom_write(
-            0x400 + self.cache.len() as u32,
-            cheksum_bytes.as_mut_ptr() as u32,
-            4,
-            &self.sys,
-        );
+        //! Stores the cache right away, only used while booting
+        self.commit_cache();
+        self.write_to_eeprom();
+    }
+
+    fn commit_cache(&mut self) {
+        //! Schedules the cache for the next EEPROM write done by `service`
+        self.uncommitted = false;
+        self.committed = self.cache;
+        self.write_pending = true;
+        self.quiet_since_ms = None;
+    }
+
+    fn write_to_eeprom(&mut self) {
+        if let Some(write) = self.take_pending_write() {
+            write.write(&self.sys);
+        }
+    }
+
+    fn take_pending_write(&mut self) -> Option<PendingWrite> {
+        if !self.write_pending && !self.names_pending {
+            return None;
+        }
+        Some(PendingWrite {
+            settings: self.write_pending.then(|| self.take_settings_write()),
+            preset_names: self.names_pending.then(|| self.take_preset_names_write()),
+        })
+    }
+
+    fn take_settings_write(&mut self) -> (u32, [u8; SLOT_SIZE]) {
+        //! The older copy is overwritten, settings, sequence number and checksum in a single write
+        self.write_pending = false;
+        self.sequence = self.sequence.wrapping_add(1);
+        let mut buffer = [0; SLOT_SIZE];
+        buffer[..60].copy_from_slice(&self.committed);
+        buffer[SEQUENCE_OFFSET..SEQUENCE_OFFSET + 2].copy_from_slice(&self.sequence.to_le_bytes());
+        let checksum = CRC.checksum(&buffer[..60]);
+        buffer[60..].copy_from_slice(&checksum.to_le_bytes());
+        self.slot = (self.slot + 1) % SLOT_ADDRS.len();
+        (SLOT_ADDRS[self.slot], buffer)
+    }
+
+    fn take_preset_names_write(&mut self) -> [u8; PRESET_NAMES_SIZE + 4] {
+        self.names_pending = false;
+        let mut buffer = [0; PRESET_NAMES_SIZE + 4];
+        buffer[..PRESET_NAMES_SIZE].copy_from_slice(&self.preset_names);
+        let checksum = CRC.checksum(&self.preset_names);
+        buffer[PRESET_NAMES_SIZE..].copy_from_slice(&checksum.to_le_bytes());
+        buffer
+    }
+
+    #[must_use]
+    pub fn service(&mut self, uptime_ms: u32) -> Option<PendingWrite> {
+        //! Called from the main loop, hands out the pending settings once the quiet period is
+        //! over. The main loop writes them after it left its critical section, so the interrupts
+        //! are not held off for the duration of the EEPROM write.
+        if !self.write_pending && !self.names_pending {
+            return None;
+        }
+        let quiet_since_ms = *self.quiet_since_ms.get_or_insert(uptime_ms);
+        let quiet = uptime_ms.wrapping_sub(quiet_since_ms) >= QUIET_PERIOD_MS;
+        let rested = self.last_write_ms.map_or(true, |last_write_ms| {
+            uptime_ms.wrapping_sub(last_write_ms) >= MIN_WRITE_INTERVAL_MS
+        });
+        if !(quiet && rested) {
+            return None;
+        }
+        self.last_write_ms = Some(uptime_ms);
+        self.take_pending_write()
+    }
+
+    pub fn flush(&mut self) {
+        //! Writes pending settings right away, before a reset would lose them
+        self.write_to_eeprom();
     }
 
     pub fn get_setting_value(&self, setting_id: u8) -> u8 {
         let setting_id = setting_id as usize;
-        if VALID_SETTING_OFFSETS.contains(&setting_id) {
+        if is_valid_setting(setting_id) {
             self.cache[setting_id]
         } else {
             0
@@ -117,34 +348,192 @@ impl Settings {
         value: u8,
         display: &mut disp::Display,
         gpio: &lpc11u6x_pac::GPIO_PORT,
+    ) -> Result<(), SettingError> {
+        self.set_setting_values(setting_id, &[value], display, gpio)
+    }
+
+    pub fn set_setting_values(
+        &mut self,
+        first_setting_id: u8,
+        values: &[u8],
+        display: &mut disp::Display,
+        gpio: &lpc11u6x_pac::GPIO_PORT,
+    ) -> Result<(), SettingError> {
+        //! Writes `values` to consecutive settings with a single EEPROM write.
+        //!
+        //! Nothing is changed if any of the settings does not exist or any value is out of its
+        //! range.
+        layout::check_values(first_setting_id, values)?;
+        let first_setting_id = first_setting_id as usize;
+        let mut changed = false;
+        for (setting_id, &value) in (first_setting_id..).zip(values) {
+            changed |= self.cache[setting_id] != value;
+            self.cache[setting_id] = value;
+            Self::apply_setting(setting_id, value, display, gpio);
+        }
+        if changed {
+            // switching the manual commit on or off is always stored, together with everything
+            // that is pending
+            let range = first_setting_id..first_setting_id + values.len();
+            if range.contains(&MANUAL_COMMIT_OFFSET) {
+                self.commit_cache();
+            } else {
+                self.store();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn export(&self) -> [u8; EXPORT_SIZE] {
+        //! The committed settings and the preset names
+        layout::export(&self.committed, &self.preset_names)
+    }
+
+    pub fn import(
+        &mut self,
+        imported: &[u8; 60],
+        names: &[u8; PRESET_NAMES_SIZE],
+        display: &mut disp::Display,
+        i2c: &I2CDriver,
+        gpio: &lpc11u6x_pac::GPIO_PORT,
     ) {
-        let setting_id = setting_id as usize;
-        if VALID_SETTING_OFFSETS.contains(&setting_id) {
-            self.write_setting(setting_id, value);
-            match setting_id {
-                DISPLAY_TOP_MODE_OFFSET => {}
-                DISPLAY_BOTTOM_MODE_OFFSET => {}
-                L1_BOOT_VOLTAGE_OFFSET => {}
-                L2_BOOT_VOLTAGE_OFFSET => {}
-                L1_ENABLED_OFFSET => {
-                    if value == 1 {
-                        crate::vrm::enable_l1(gpio);
-                    } else {
-                        crate::vrm::disable_l1(gpio);
-                    }
-                }
-                L2_ENABLED_OFFSET => {
-                    if value == 1 {
-                        crate::vrm::enable_l2(gpio);
-                    } else {
-                        crate::vrm::disable_l2(gpio);
-                    }
+        //! Replaces all settings with those of an export checked by
+        //! `epower_protocol::settings::parse_export` and stores them without a commit
+        for setting_id in (0..60).filter(|&setting_id| is_valid_setting(setting_id)) {
+            self.cache[setting_id] = imported[setting_id];
+        }
+        self.store_preset_names(*names);
+        self.commit_cache();
+        self.apply_all(display, i2c, gpio);
+    }
+
+    pub fn preset_name(&self, number: u8) -> Option<[u8; PRESET_NAME_SIZE]> {
+        //! Only the presets 1 to `PRESET_COUNT` have a name
+        let index = (number as usize).checked_sub(1)?;
+        self.preset_names
+            .chunks_exact(PRESET_NAME_SIZE)
+            .nth(index)
+            .map(|name| name.try_into().expect("name is PRESET_NAME_SIZE bytes"))
+    }
+
+    pub fn set_preset_name(&mut self, number: u8, name: &[u8]) -> Result<(), SettingError> {
+        //! `name` is padded with 0, an empty name removes it
+        layout::check_name(number, name)?;
+        let index = number as usize - 1;
+        let mut names = self.preset_names;
+        let padded = &mut names[index * PRESET_NAME_SIZE..(index + 1) * PRESET_NAME_SIZE];
+        padded.fill(0);
+        padded[..name.len()].copy_from_slice(name);
+        self.store_preset_names(names);
+        Ok(())
+    }
+
+    fn store_preset_names(&mut self, names: [u8; PRESET_NAMES_SIZE]) {
+        //! Written together with the settings by `service`
+        if self.preset_names != names {
+            self.preset_names = names;
+            self.names_pending = true;
+            self.quiet_since_ms = None;
+        }
+    }
+
+    fn store(&mut self) {
+        if self.is_manual_commit_enabled() {
+            self.uncommitted = true;
+        } else {
+            self.commit_cache();
+        }
+    }
+
+    pub fn is_manual_commit_enabled(&self) -> bool {
+        self.cache[MANUAL_COMMIT_OFFSET] == 1
+    }
+
+    pub fn has_uncommitted_changes(&self) -> bool {
+        self.uncommitted
+    }
+
+    pub fn commit(&mut self) {
+        if self.uncommitted {
+            self.commit_cache();
+        }
+    }
+
+    pub fn discard(
+        &mut self,
+        display: &mut disp::Display,
+        i2c: &I2CDriver,
+        gpio: &lpc11u6x_pac::GPIO_PORT,
+    ) {
+        //! Goes back to the committed settings and applies them like they were written again
+        if !self.uncommitted {
+            return;
+        }
+        self.cache = self.committed;
+        self.uncommitted = false;
+        self.apply_all(display, i2c, gpio);
+    }
+
+    pub fn restore_defaults(
+        &mut self,
+        display: &mut disp::Display,
+        i2c: &I2CDriver,
+        gpio: &lpc11u6x_pac::GPIO_PORT,
+    ) {
+        //! Stores `default_settings()` without a commit, manual commit is off afterwards anyway.
+        //! The presets lose their names as well.
+        self.cache.copy_from_slice(&default_settings()[0..60]);
+        self.store_preset_names([0; PRESET_NAMES_SIZE]);
+        self.commit_cache();
+        self.apply_all(display, i2c, gpio);
+    }
+
+    fn apply_all(
+        &self,
+        display: &mut disp::Display,
+        i2c: &I2CDriver,
+        gpio: &lpc11u6x_pac::GPIO_PORT,
+    ) {
+        //! The loops are set to the active preset once more, with its new values, so that they
+        //! still match what 0xE0 reports
+        for (setting_id, &value) in self.cache.iter().enumerate() {
+            let drives_loop = matches!(setting_id, L1_ENABLED_OFFSET | L2_ENABLED_OFFSET);
+            if is_valid_setting(setting_id) && !drives_loop {
+                Self::apply_setting(setting_id, value, display, gpio);
+            }
+        }
+        self.preset_at(self.active_preset).apply(i2c, gpio);
+    }
+
+    fn apply_setting(
+        setting_id: usize,
+        value: u8,
+        display: &mut disp::Display,
+        gpio: &lpc11u6x_pac::GPIO_PORT,
+    ) {
+        match setting_id {
+            DISPLAY_TOP_MODE_OFFSET => {}
+            DISPLAY_BOTTOM_MODE_OFFSET => {}
+            L1_BOOT_VOLTAGE_OFFSET => {}
+            L2_BOOT_VOLTAGE_OFFSET => {}
+            L1_ENABLED_OFFSET => {
+                if value == 1 {
+                    crate::vrm::enable_l1(gpio);
+                } else {
+                    crate::vrm::disable_l1(gpio);
                 }
-                DISPLAY_ORIENTATION_OFFSET => {
-                    display.set_display_orientation(value.into());
+            }
+            L2_ENABLED_OFFSET => {
+                if value == 1 {
+                    crate::vrm::enable_l2(gpio);
+                } else {
+                    crate::vrm::disable_l2(gpio);
                 }
-                _ => {}
             }
+            DISPLAY_ORIENTATION_OFFSET => {
+                display.set_display_orientation(value.into());
+            }
+            _ => {}
         }
     }
 }
@@ -153,7 +542,7 @@ impl Settings {
     fn write_setting(&mut self, setting_id: usize, value: u8) {
         if self.cache[setting_id] != value {
             self.cache[setting_id] = value;
-            self.write_cache_to_eeprom();
+            self.store();
         }
     }
 
@@ -196,4 +585,115 @@ impl Settings {
     pub fn set_display_orientation(&mut self, orient: Orientation) {
         self.write_setting(DISPLAY_ORIENTATION_OFFSET, orient.into());
     }
+
+    pub fn boot_preset_number(&self) -> u8 {
+        let number = self.cache[BOOT_PRESET_OFFSET];
+        if number <= PRESET_COUNT {
+            number
+        } else {
+            0
+        }
+    }
+    pub fn boot_preset(&self) -> Preset {
+        self.preset_at(self.boot_preset_number())
+    }
+
+    pub fn preset(&self, number: u8) -> Option<Preset> {
+        if number <= PRESET_COUNT {
+            Some(self.preset_at(number))
+        } else {
+            None
+        }
+    }
+
+    fn preset_at(&self, number: u8) -> Preset {
+        //! Preset 0 maps onto the boot voltage and enable settings
+        if number == 0 {
+            return Preset {
+                l1_vid: self.cache[L1_BOOT_VOLTAGE_OFFSET],
+                l2_vid: self.cache[L2_BOOT_VOLTAGE_OFFSET],
+                l1_enabled: self.cache[L1_ENABLED_OFFSET] == 1,
+                l2_enabled: self.cache[L2_ENABLED_OFFSET] == 1,
+            };
+        }
+        let base = PRESETS_OFFSET + (number as usize - 1) * PRESET_SIZE;
+        Preset {
+            l1_vid: self.cache[base + PRESET_L1_VID],
+            l2_vid: self.cache[base + PRESET_L2_VID],
+            l1_enabled: self.cache[base + PRESET_L1_ENABLED] == 1,
+            l2_enabled: self.cache[base + PRESET_L2_ENABLED] == 1,
+        }
+    }
+
+    pub fn proxy_address(&self) -> Option<u8> {
+        //! The Epower wins should both addresses be the same
+        slave_address(self.cache[PROXY_ADDRESS_OFFSET], crate::iic::IR3595_ADDR)
+            .filter(|&addr| Some(addr) != self.epower_address())
+    }
+    pub fn epower_address(&self) -> Option<u8> {
+        slave_address(self.cache[EPOWER_ADDRESS_OFFSET], crate::iic::EPOWER_ADDR)
+    }
+    pub fn reset_addresses(&mut self) {
+        if self.cache[PROXY_ADDRESS_OFFSET] != 0 || self.cache[EPOWER_ADDRESS_OFFSET] != 0 {
+            self.cache[PROXY_ADDRESS_OFFSET] = 0;
+            self.cache[EPOWER_ADDRESS_OFFSET] = 0;
+            self.write_cache_to_eeprom();
+        }
+    }
+
+    pub fn syscon(&self) -> &lpc11u6x_pac::SYSCON {
+        //! The IAP routines need the system clock
+        &self.sys
+    }
+
+    pub fn boot_count(&self) -> u32 {
+        self.boot_count
+    }
+
+    pub fn active_preset(&self) -> u8 {
+        self.active_preset
+    }
+
+    pub fn select_preset(
+        &mut self,
+        number: u8,
+        i2c: &I2CDriver,
+        gpio: &lpc11u6x_pac::GPIO_PORT,
+    ) -> bool {
+        match self.preset(number) {
+            Some(preset) => {
+                preset.apply(i2c, gpio);
+                self.active_preset = number;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn step_preset(&mut self, forward: bool, i2c: &I2CDriver, gpio: &lpc11u6x_pac::GPIO_PORT) {
+        //! Skips unused presets, so stepping through them never switches a loop off by accident.
+        //! Preset 0 is always there.
+        let count = PRESET_COUNT + 1;
+        let active = self.active_preset;
+        let step = (1..count)
+            .map(|step| {
+                if forward {
+                    (active + step) % count
+                } else {
+                    (active + count - step) % count
+                }
+            })
+            .find(|&number| number == 0 || !self.preset_at(number).is_unused());
+        if let Some(number) = step {
+            self.select_preset(number, i2c, gpio);
+        }
+    }
+
+    pub fn select_next_preset(&mut self, i2c: &I2CDriver, gpio: &lpc11u6x_pac::GPIO_PORT) {
+        self.step_preset(true, i2c, gpio);
+    }
+
+    pub fn select_previous_preset(&mut self, i2c: &I2CDriver, gpio: &lpc11u6x_pac::GPIO_PORT) {
+        self.step_preset(false, i2c, gpio);
+    }
 }
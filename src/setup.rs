@@ -30,10 +30,10 @@ pub fn setup_io_pins(iocon: &lpc11u6x_pac::IOCON, gpio: &lpc11u6x_pac::GPIO_PORT
     gpio.dir[1].write(|w| w.dirp27().set_bit());
     gpio.dir[2].write(|w| w.dirp2().set_bit());
 
-    //PGOOD NVVDD
+    //PGOOD NVVDD, function 0 is TDO
     iocon
         .pio0_13()
-        .modify(|_r, w| w.mode().inactive_no_pull_do());
+        .modify(|_r, w| unsafe { w.mode().inactive_no_pull_do().func().bits(1) });
     //PGOOD NVVDDS
     iocon.pio1_[10].modify(|_r, w| w.mode().inactive_no_pull_do());
 
@@ -64,12 +64,7 @@ pub fn setup_io_pins(iocon: &lpc11u6x_pac::IOCON, gpio: &lpc11u6x_pac::GPIO_PORT
     iocon.pio0_19().modify(|_r, w| unsafe { w.func().bits(1) });
 
     //i2c0
-    iocon
-        .pio0_4
-        .write(|w| unsafe { w.func().bits(1 | (1 << 7)) });
-    iocon
-        .pio0_5
-        .write(|w| unsafe { w.func().bits(1 | (1 << 7)) });
+    select_i2c0_pin_function(iocon);
 
     //i2c1
     iocon
@@ -84,6 +79,21 @@ pub fn setup_io_pins(iocon: &lpc11u6x_pac::IOCON, gpio: &lpc11u6x_pac::GPIO_PORT
     gpio.dir[0].write(|w| w.dirp6().set_bit());
 }
 
+pub fn select_i2c0_pin_function(iocon: &lpc11u6x_pac::IOCON) {
+    iocon
+        .pio0_4
+        .write(|w| unsafe { w.func().bits(1 | (1 << 7)) });
+    iocon
+        .pio0_5
+        .write(|w| unsafe { w.func().bits(1 | (1 << 7)) });
+}
+
+pub fn select_i2c0_gpio_function(iocon: &lpc11u6x_pac::IOCON) {
+    //! PIO0_4 and PIO0_5 are true open drain pins, as GPIO they can only pull low
+    iocon.pio0_4.write(|w| unsafe { w.func().bits(0) });
+    iocon.pio0_5.write(|w| unsafe { w.func().bits(0) });
+}
+
 #[allow(dead_code)]
 pub fn restore_gpio_after_bootloader(periph: &lpc11u6x_pac::Peripherals) {
     //! Reset the registers modified by the bootloader back to default bootup values.
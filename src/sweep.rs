@@ -0,0 +1,211 @@
+use crate::rom::i2c::I2CDriver;
+use crate::telemetry::{Snapshot, SAMPLE_RATE_HZ};
+use crate::vrm::{self, Loop};
+use epower_protocol::settings::MAX_VID;
+
+pub const MAX_RECORDS: usize = 128;
+pub const RECORD_SIZE: usize = 6;
+/// Steps are measured with the telemetry samples, shorter dwell times cannot be kept
+pub const MIN_DWELL_MS: u16 = (1000 / SAMPLE_RATE_HZ) as u16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SweepConfig {
+    pub output: Loop,
+    pub start_vid: u8,
+    pub stop_vid: u8,
+    pub step: u8,
+    pub dwell_ms: u16,
+}
+
+impl SweepConfig {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        //! Expects loop, start VID, stop VID, VID step and the dwell time in ms, MSB first.
+        //!
+        //! The dwell time has to be at least `MIN_DWELL_MS`, start and stop VID at most `MAX_VID`.
+        if bytes.len() < 6 || bytes[3] == 0 || bytes[1] > MAX_VID || bytes[2] > MAX_VID {
+            return None;
+        }
+        let dwell_ms = u16::from_be_bytes([bytes[4], bytes[5]]);
+        if dwell_ms < MIN_DWELL_MS {
+            return None;
+        }
+        Some(SweepConfig {
+            output: Loop::try_from(bytes[0]).ok()?,
+            start_vid: bytes[1],
+            stop_vid: bytes[2],
+            step: bytes[3],
+            dwell_ms,
+        })
+    }
+
+    fn next_vid(&self, vid: u8) -> Option<u8> {
+        if self.start_vid <= self.stop_vid {
+            vid.checked_add(self.step).filter(|&v| v <= self.stop_vid)
+        } else {
+            vid.checked_sub(self.step).filter(|&v| v >= self.stop_vid)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Record {
+    pub vid: u8,
+    pub voltage_mv: u16,
+    pub current_a: u16,
+    pub temp_c: u8,
+}
+
+impl Record {
+    pub fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let [voltage_high, voltage_low] = self.voltage_mv.to_be_bytes();
+        let [current_high, current_low] = self.current_a.to_be_bytes();
+        [
+            self.vid,
+            voltage_high,
+            voltage_low,
+            current_high,
+            current_low,
+            self.temp_c,
+        ]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    Running,
+    Done,
+    Aborted,
+}
+
+impl From<State> for u8 {
+    fn from(state: State) -> u8 {
+        match state {
+            State::Idle => 0,
+            State::Running => 1,
+            State::Done => 2,
+            State::Aborted => 3,
+        }
+    }
+}
+
+pub struct Sweep {
+    config: Option<SweepConfig>,
+    state: State,
+    vid: u8,
+    restore_vid: u8,
+    step_started_ms: u32,
+    records: [Record; MAX_RECORDS],
+    record_count: usize,
+}
+
+impl Sweep {
+    pub fn new() -> Self {
+        Sweep {
+            config: None,
+            state: State::Idle,
+            vid: 0,
+            restore_vid: 0,
+            step_started_ms: 0,
+            records: [Record::default(); MAX_RECORDS],
+            record_count: 0,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.record_count
+    }
+
+    pub fn configure(&mut self, config: SweepConfig) -> bool {
+        if self.state == State::Running {
+            return false;
+        }
+        self.config = Some(config);
+        true
+    }
+
+    pub fn start(&mut self, i2c: &I2CDriver, now_ms: u32) -> bool {
+        let Some(config) = self.config else {
+            return false;
+        };
+        if self.state == State::Running {
+            return false;
+        }
+        let Some(restore_vid) = vrm::read_vid(i2c, config.output) else {
+            return false;
+        };
+        self.restore_vid = restore_vid;
+        self.record_count = 0;
+        self.vid = config.start_vid;
+        vrm::set_voltage_raw(i2c, config.output, self.vid);
+        self.step_started_ms = now_ms;
+        self.state = State::Running;
+        true
+    }
+
+    pub fn abort(&mut self, i2c: &I2CDriver) {
+        if self.state == State::Running {
+            self.finish(i2c, State::Aborted);
+        }
+    }
+
+    pub fn tick(&mut self, i2c: &I2CDriver, snapshot: &Snapshot) {
+        //! Called for every telemetry sample, records the first sample taken after the dwell time
+        //! of a step has passed. The dwell time is therefore rounded up to the sample interval.
+        if self.state != State::Running {
+            return;
+        }
+        let Some(config) = self.config else {
+            return;
+        };
+        let now_ms = snapshot.timestamp_ms;
+        if now_ms.wrapping_sub(self.step_started_ms) < config.dwell_ms as u32 {
+            return;
+        }
+
+        let telemetry = snapshot.output(config.output);
+        self.records[self.record_count] = Record {
+            vid: self.vid,
+            voltage_mv: telemetry.voltage_mv as u16,
+            current_a: telemetry.current_a,
+            temp_c: telemetry.temp_c,
+        };
+        self.record_count += 1;
+
+        match config.next_vid(self.vid) {
+            Some(vid) if self.record_count < MAX_RECORDS => {
+                self.vid = vid;
+                vrm::set_voltage_raw(i2c, config.output, vid);
+                self.step_started_ms = now_ms;
+            }
+            _ => self.finish(i2c, State::Done),
+        }
+    }
+
+    pub fn record_byte(&self, index: usize) -> Option<u8> {
+        //! Byte `index` of all recorded steps laid out back to back
+        let record = index / RECORD_SIZE;
+        if record < self.record_count {
+            Some(self.records[record].to_bytes()[index % RECORD_SIZE])
+        } else {
+            None
+        }
+    }
+
+    fn finish(&mut self, i2c: &I2CDriver, state: State) {
+        if let Some(config) = self.config {
+            vrm::set_voltage_raw(i2c, config.output, self.restore_vid);
+        }
+        self.state = state;
+    }
+}
+
+impl Default for Sweep {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,121 @@
+use crate::adc::Adc;
+use crate::rom::i2c::I2CDriver;
+use crate::vrm::{self, Loop};
+
+/// Rate at which CT16B1 triggers a new sample
+pub const SAMPLE_RATE_HZ: u32 = 10;
+
+/// ADC channels in the order of the Epower monitoring registers:
+/// L1 output, L2 output, 12V, 5V and 3V
+const ADC_CHANNELS: [usize; 5] = [2, 1, 8, 10, 9];
+pub const ADC_CHANNEL_COUNT: usize = ADC_CHANNELS.len();
+
+/// Samples in a row with failed VRM reads after which I2C0 is considered stuck
+const STUCK_BUS_SAMPLES: u8 = 3;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LoopTelemetry {
+    pub voltage_mv: f32,
+    pub current_a: u16,
+    pub temp_c: u8,
+}
+
+impl LoopTelemetry {
+    fn read(i2c: &I2CDriver, output: Loop) -> Option<Self> {
+        Some(LoopTelemetry {
+            voltage_mv: vrm::read_voltage(i2c, output)?,
+            current_a: vrm::read_current(i2c, output)?,
+            temp_c: vrm::read_temp(i2c, output)?,
+        })
+    }
+
+    pub fn power_mw(&self) -> u32 {
+        crate::energy::power_mw(self.voltage_mv, self.current_a)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Snapshot {
+    pub timestamp_ms: u32,
+    pub loops: [LoopTelemetry; 2],
+    pub adc_mv: [u16; ADC_CHANNEL_COUNT],
+    /// Active IR3595 faults, see `vrm::faults`
+    pub faults: u16,
+    pub enabled: [bool; 2],
+    pub power_good: [bool; 2],
+}
+
+impl Snapshot {
+    pub fn output(&self, output: Loop) -> &LoopTelemetry {
+        &self.loops[output as usize]
+    }
+}
+
+/// Reads all VRM and ADC values at a fixed rate so that consumers never block on the buses
+pub struct Sampler {
+    adc: Adc,
+    snapshot: Snapshot,
+    sample_count: u32,
+    failed_samples: u8,
+    bus_recoveries: u16,
+}
+
+impl Sampler {
+    pub fn new(adc: Adc) -> Self {
+        Sampler {
+            adc,
+            snapshot: Snapshot::default(),
+            sample_count: 0,
+            failed_samples: 0,
+            bus_recoveries: 0,
+        }
+    }
+
+    pub fn sample(
+        &mut self,
+        i2c: &I2CDriver,
+        gpio: &lpc11u6x_pac::GPIO_PORT,
+        now_ms: u32,
+    ) -> &Snapshot {
+        self.snapshot.timestamp_ms = now_ms;
+        let l1 = LoopTelemetry::read(i2c, Loop::L1);
+        let l2 = LoopTelemetry::read(i2c, Loop::L2);
+        if l1.is_some() && l2.is_some() {
+            self.failed_samples = 0;
+        } else {
+            self.failed_samples = self.failed_samples.saturating_add(1);
+        }
+        self.snapshot.loops = [l1.unwrap_or_default(), l2.unwrap_or_default()];
+        self.snapshot.faults = vrm::faults::read_all(i2c).unwrap_or(0);
+        for output in [Loop::L1, Loop::L2] {
+            self.snapshot.enabled[output as usize] = vrm::is_enabled(gpio, output);
+            self.snapshot.power_good[output as usize] = vrm::is_power_good(gpio, output);
+        }
+        for (value, channel) in self.snapshot.adc_mv.iter_mut().zip(ADC_CHANNELS) {
+            *value = self.adc.read_channel(channel);
+        }
+        self.sample_count = self.sample_count.wrapping_add(1);
+        &self.snapshot
+    }
+
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn is_bus_stuck(&self) -> bool {
+        self.failed_samples >= STUCK_BUS_SAMPLES
+    }
+
+    pub fn bus_recovered(&mut self) {
+        self.failed_samples = 0;
+        self.bus_recoveries = self.bus_recoveries.saturating_add(1);
+    }
+
+    pub fn bus_recoveries(&self) -> u16 {
+        self.bus_recoveries
+    }
+}
@@ -0,0 +1,245 @@
+use crate::boot;
+use crate::rom::{flash, IapStatus};
+
+/// A firmware image covers sectors 0 to 24, see memory.x
+pub const APPLICATION_SIZE: u32 = 0x2_0000;
+/// Sector 0 of an image holds the bootloader, which an update leaves in place, see boot.rs
+pub const APPLICATION_ADDR: u32 = 0x1000;
+pub const FIRST_APPLICATION_SECTOR: u32 = 1;
+pub const LAST_APPLICATION_SECTOR: u32 = 24;
+/// A new image is received into sectors 25 to 28 before it replaces the firmware
+pub const STAGING_ADDR: u32 = 0x2_0000;
+const FIRST_STAGING_SECTOR: u32 = 25;
+const LAST_STAGING_SECTOR: u32 = 28;
+/// Smallest unit the IAP routines can program
+pub const PAGE_SIZE: usize = 256;
+/// Image bytes carried by one data block, only the last block may be shorter
+pub const BLOCK_SIZE: usize = 32;
+pub const RAM_START: u32 = 0x1000_0000;
+pub const RAM_END: u32 = 0x1000_8000;
+
+/// CRC of the whole image, the same as zlib's crc32
+const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    Receiving,
+    /// The staged image matches its CRC and can be installed
+    Verified,
+    /// The image is empty or does not fit into the firmware sectors
+    LengthError,
+    /// A block was skipped or had the wrong length
+    SequenceError,
+    /// Erasing or programming the staging sectors failed
+    FlashError,
+    CrcMismatch,
+    /// The vector table of the firmware does not point into RAM and into the image
+    InvalidImage,
+    /// The staging sectors are erased one after the other, blocks are accepted afterwards
+    Erasing,
+    /// The last page is programmed and the image is checked
+    Verifying,
+}
+
+impl From<State> for u8 {
+    fn from(state: State) -> u8 {
+        match state {
+            State::Idle => 0,
+            State::Receiving => 1,
+            State::Verified => 2,
+            State::LengthError => 3,
+            State::SequenceError => 4,
+            State::FlashError => 5,
+            State::CrcMismatch => 6,
+            State::InvalidImage => 7,
+            State::Erasing => 8,
+            State::Verifying => 9,
+        }
+    }
+}
+
+/// IAP sources have to be word aligned
+#[repr(align(4))]
+struct Page([u8; PAGE_SIZE]);
+
+/// Receives a firmware image block by block into the staging sectors.
+///
+/// The blocks arrive in the I2C1 interrupt, erasing and programming the flash is left to
+/// `service` in the main loop.
+pub struct Updater {
+    state: State,
+    length: u32,
+    crc: u32,
+    received: u32,
+    next_block: u16,
+    page: Page,
+    /// Next staging sector to erase while `Erasing`
+    erase_sector: u32,
+    /// `page` is complete and waits to be programmed
+    page_full: bool,
+    /// The host asked for the verified image to be installed
+    install_requested: bool,
+}
+
+impl Updater {
+    pub fn new() -> Self {
+        Updater {
+            state: State::Idle,
+            length: 0,
+            crc: 0,
+            received: 0,
+            next_block: 0,
+            page: Page([0xFF; PAGE_SIZE]),
+            erase_sector: FIRST_STAGING_SECTOR,
+            page_full: false,
+            install_requested: false,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn next_block(&self) -> u16 {
+        self.next_block
+    }
+
+    pub fn start(&mut self, length: u32, crc: u32) {
+        //! The staging sectors are erased by `service` afterwards
+        self.length = length;
+        self.crc = crc;
+        self.received = 0;
+        self.next_block = 0;
+        self.page.0.fill(0xFF);
+        self.page_full = false;
+        self.install_requested = false;
+        if length == 0 || length > APPLICATION_SIZE {
+            self.state = State::LengthError;
+            return;
+        }
+        self.erase_sector = FIRST_STAGING_SECTOR;
+        self.state = State::Erasing;
+    }
+
+    pub fn write_block(&mut self, block: u16, data: &[u8]) {
+        //! A repeated block is ignored, so a block can be resent when its ACK got lost. So is a
+        //! block that arrives while the previous page is still programmed, the next block number
+        //! tells the host to send it again.
+        if self.state != State::Receiving
+            || self.page_full
+            || block.wrapping_add(1) == self.next_block
+        {
+            return;
+        }
+        let expected_len = (self.length - self.received).min(BLOCK_SIZE as u32) as usize;
+        if block != self.next_block || data.len() != expected_len {
+            self.state = State::SequenceError;
+            return;
+        }
+
+        let page_offset = self.received as usize % PAGE_SIZE;
+        self.page.0[page_offset..page_offset + data.len()].copy_from_slice(data);
+        self.received += data.len() as u32;
+        self.next_block += 1;
+
+        let finished = self.received == self.length;
+        self.page_full = page_offset + data.len() == PAGE_SIZE || finished;
+        if finished {
+            self.state = State::Verifying;
+        }
+    }
+
+    pub fn service(&mut self, sys: &lpc11u6x_pac::SYSCON) {
+        //! Called from the main loop, runs one pending flash step: erasing one sector, programming
+        //! one page or verifying the image. The EVBot bus is held off while a step runs, a
+        //! single step keeps that short.
+        //!
+        //! The flash cannot be read while it is erased or programmed, so this has to run with all
+        //! interrupts disabled.
+        match self.state {
+            State::Erasing => {
+                let sector = self.erase_sector;
+                let erased = flash::prepare_sectors(sector, sector) == IapStatus::CmdSuccess
+                    && flash::erase_sectors(sector, sector, sys) == IapStatus::CmdSuccess;
+                self.erase_sector += 1;
+                if !erased {
+                    self.state = State::FlashError;
+                } else if sector == LAST_STAGING_SECTOR {
+                    self.state = State::Receiving;
+                }
+            }
+            State::Receiving | State::Verifying if self.page_full => {
+                let page_addr =
+                    STAGING_ADDR + (self.received - 1) / PAGE_SIZE as u32 * PAGE_SIZE as u32;
+                let programmed = flash::prepare_sectors(FIRST_STAGING_SECTOR, LAST_STAGING_SECTOR)
+                    == IapStatus::CmdSuccess
+                    && flash::copy_ram_to_flash(page_addr, &self.page.0, sys)
+                        == IapStatus::CmdSuccess;
+                self.page.0.fill(0xFF);
+                self.page_full = false;
+                if !programmed {
+                    self.state = State::FlashError;
+                }
+            }
+            State::Verifying => self.state = self.verify(),
+            _ => {}
+        }
+    }
+
+    fn verify(&self) -> State {
+        let image =
+            unsafe { core::slice::from_raw_parts(STAGING_ADDR as *const u8, self.length as usize) };
+        if CRC.checksum(image) != self.crc {
+            return State::CrcMismatch;
+        }
+        // initial stack pointer and reset vector of the firmware behind the bootloader, as a last
+        // check against a wrong file
+        let Some(vectors) = image.get(APPLICATION_ADDR as usize..APPLICATION_ADDR as usize + 8)
+        else {
+            return State::InvalidImage;
+        };
+        let stack_pointer = u32::from_le_bytes([vectors[0], vectors[1], vectors[2], vectors[3]]);
+        let reset_vector = u32::from_le_bytes([vectors[4], vectors[5], vectors[6], vectors[7]]);
+        if !(RAM_START..=RAM_END).contains(&stack_pointer)
+            || !(APPLICATION_ADDR + 8..self.length).contains(&(reset_vector & !1))
+        {
+            return State::InvalidImage;
+        }
+        State::Verified
+    }
+
+    pub fn request_install(&mut self) {
+        //! Only a verified image is installed
+        self.install_requested = self.state == State::Verified;
+    }
+
+    pub fn take_install_request(&mut self) -> bool {
+        core::mem::take(&mut self.install_requested)
+    }
+
+    pub fn install(&self, sys: &lpc11u6x_pac::SYSCON) {
+        //! Leaves an install request for the bootloader and resets into it, which replaces the
+        //! firmware with the verified image. Does nothing when there is no verified image.
+        if self.state != State::Verified {
+            return;
+        }
+        let mut request = [0; 12];
+        request[0..4].copy_from_slice(&boot::INSTALL_REQUESTED.to_le_bytes());
+        request[4..8].copy_from_slice(&self.length.to_le_bytes());
+        request[8..12].copy_from_slice(&self.crc.to_le_bytes());
+        crate::rom::eeprom::eeprom_write(
+            boot::INSTALL_REQUEST_ADDR,
+            request.as_mut_ptr() as u32,
+            request.len() as u32,
+            sys,
+        );
+        cortex_m::peripheral::SCB::sys_reset()
+    }
+}
+
+impl Default for Updater {
+    fn default() -> Self {
+        Self::new()
+    }
+}
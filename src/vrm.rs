@@ -2,6 +2,23 @@ use crate::i2c::{I2CDriver, I2cParam, I2cResult};
 use crate::iic::IR3595_ADDR_SHIFTED;
 use crate::rom::ErrorCode;
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Loop {
+    L1,
+    L2,
+}
+
+impl TryFrom<u8> for Loop {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Loop::L1),
+            1 => Ok(Loop::L2),
+            _ => Err(value),
+        }
+    }
+}
+
 pub enum DeviceMode {
     Nvidia,
     InternationalBusinessMachine,
@@ -165,9 +182,27 @@ pub fn disable_l2(gpio: &lpc11u6x_pac::GPIO_PORT) {
     gpio.dir[1].modify(|_r, w| w.dirp27().set_bit());
 }
 
+pub fn is_enabled(gpio: &lpc11u6x_pac::GPIO_PORT, output: Loop) -> bool {
+    match output {
+        Loop::L1 => gpio.dir[2].read().dirp2().bit_is_clear(),
+        Loop::L2 => gpio.dir[1].read().dirp27().bit_is_clear(),
+    }
+}
+
+pub fn is_power_good(gpio: &lpc11u6x_pac::GPIO_PORT, output: Loop) -> bool {
+    //! PGOOD of NVVDD is connected to PIO0_13, PGOOD of NVVDDS to PIO1_10
+    match output {
+        Loop::L1 => gpio.pin[0].read().bits() & (1 << 13) != 0,
+        Loop::L2 => gpio.pin[1].read().bits() & (1 << 10) != 0,
+    }
+}
+
 pub fn read_vid_l1(i2c: &I2CDriver) -> Option<u8> {
     read_reg(i2c, 0x7A)
 }
+pub fn read_vid_l2(i2c: &I2CDriver) -> Option<u8> {
+    read_reg(i2c, 0x7C)
+}
 pub fn set_voltage_l1(i2c: &I2CDriver, voltage_mv: u16) {
     let voltage_encoded: u8 = (((voltage_mv) as f32) * 0.08) as u8;
     write_reg(i2c, 0x7A, voltage_encoded);
@@ -190,6 +225,37 @@ pub fn set_offset_l2(i2c: &I2CDriver, voltage_mv: i16) {
 pub fn enable_dvid(i2c: &I2CDriver) {
     write_reg(i2c, 0x78, 0x86);
 }
+
+pub fn read_vid(i2c: &I2CDriver, output: Loop) -> Option<u8> {
+    match output {
+        Loop::L1 => read_vid_l1(i2c),
+        Loop::L2 => read_vid_l2(i2c),
+    }
+}
+pub fn set_voltage_raw(i2c: &I2CDriver, output: Loop, voltage: u8) {
+    match output {
+        Loop::L1 => set_voltage_l1_raw(i2c, voltage),
+        Loop::L2 => set_voltage_l2_raw(i2c, voltage),
+    }
+}
+pub fn read_voltage(i2c: &I2CDriver, output: Loop) -> Option<f32> {
+    match output {
+        Loop::L1 => read_voltage_l1(i2c),
+        Loop::L2 => read_voltage_l2(i2c),
+    }
+}
+pub fn read_current(i2c: &I2CDriver, output: Loop) -> Option<u16> {
+    match output {
+        Loop::L1 => read_current_l1(i2c),
+        Loop::L2 => read_current_l2(i2c),
+    }
+}
+pub fn read_temp(i2c: &I2CDriver, output: Loop) -> Option<u8> {
+    match output {
+        Loop::L1 => read_temp_l1(i2c),
+        Loop::L2 => read_temp_l2(i2c),
+    }
+}
 /*pub fn stupid_test(i2c: &I2CDriver) {
     let mut buffer1 = [0x23u8 << 1, 3, 0];
     let mut buffer2 = [0x23u8 << 1, 1, 40];
@@ -223,6 +289,47 @@ fn bit_set(data: u8, bit: u8) -> bool {
 }
 pub mod faults {
     use super::*;
+
+    // Bits of the packed fault word returned by `read_all`
+    pub const INPUT_UNDER_VOLTAGE: u16 = 1 << 0;
+    pub const OVER_TEMP_L1: u16 = 1 << 1;
+    pub const OVER_TEMP_L2: u16 = 1 << 2;
+    pub const OVER_CURRENT_L1: u16 = 1 << 3;
+    pub const OVER_CURRENT_L2: u16 = 1 << 4;
+    pub const OVER_VOLTAGE_L1: u16 = 1 << 5;
+    pub const OVER_VOLTAGE_L2: u16 = 1 << 6;
+    pub const OUTPUT_OFF_L1: u16 = 1 << 7;
+    pub const OUTPUT_OFF_L2: u16 = 1 << 8;
+    pub const POWER_GOOD_ERROR_L1: u16 = 1 << 9;
+    pub const POWER_GOOD_ERROR_L2: u16 = 1 << 10;
+
+    pub fn read_all(i2c: &I2CDriver) -> Option<u16> {
+        //! All faults below packed into one word, reading each status register only once
+        let pgood_l1 = read_reg(i2c, 0xC2)?;
+        let status_l1 = read_reg(i2c, 0xC3)?;
+        let pgood_l2 = read_reg(i2c, 0xC5)?;
+        let status_l2 = read_reg(i2c, 0xC6)?;
+        let faults = [
+            (bit_set(status_l1, 3), INPUT_UNDER_VOLTAGE),
+            (bit_set(status_l1, 2), OVER_TEMP_L1),
+            (bit_set(status_l2, 2), OVER_TEMP_L2),
+            (bit_set(status_l1, 4), OVER_CURRENT_L1),
+            (bit_set(status_l2, 4), OVER_CURRENT_L2),
+            (bit_set(status_l1, 5), OVER_VOLTAGE_L1),
+            (bit_set(status_l2, 5), OVER_VOLTAGE_L2),
+            (bit_set(status_l1, 6), OUTPUT_OFF_L1),
+            (bit_set(status_l2, 6), OUTPUT_OFF_L2),
+            (bit_set(pgood_l1, 3), POWER_GOOD_ERROR_L1),
+            (bit_set(pgood_l2, 3), POWER_GOOD_ERROR_L2),
+        ];
+        Some(
+            faults
+                .iter()
+                .filter(|(set, _)| *set)
+                .fold(0, |word, (_, bit)| word | bit),
+        )
+    }
+
     pub fn input_under_voltage(i2c: &I2CDriver) -> Option<bool> {
         Some(bit_set(read_reg(i2c, 0xC3)?, 3))
     }